@@ -0,0 +1,433 @@
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::Path,
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{
+    clients::Ban, message::MessageType, registry::Registry, server::Relay, stats::Stats, unix_time,
+};
+
+/*
+    Line based admin protocol. Every command is answered with zero or more data lines,
+    followed by either `OK` or `ERROR <reason>`.
+
+    LIST                    -> `<key> <connection_id> [device]` per connection
+    KICK <connection_id>    -> closes a single connection
+    KICKKEY <key>           -> closes all connections of a key
+    BROADCAST <text>        -> sends `NOTICE <text>` to every connection
+    BAN <key> <hours|permanent> [reason]
+                            -> disables the account, closes all of its connections and
+                               cancels its scheduled boops
+    UNBAN <key>             -> lifts the ban of an account
+    RELOAD                  -> re-reads the clients config and motd files,
+                               closes the connections of banned keys
+    STATS                   -> `<name> <value>` per counter
+    PAIRSTATS [key]         -> `<from> <to> <total> <today> <streak> <longest_streak>` per pair,
+                               only the pairs of the key if given
+*/
+
+#[derive(Debug, PartialEq)]
+enum AdminCommand {
+    List,
+    Kick(String),                             //connection_id
+    KickKey(String),                          //key
+    Broadcast(String),                        //text
+    Ban(String, Option<u64>, Option<String>), //key, hours (permanent if not set), reason
+    Unban(String),                            //key
+    Reload,
+    Stats,
+    PairStats(Option<String>), //key
+}
+
+fn parse_command(line: &str) -> Result<AdminCommand, String> {
+    let line = line.trim();
+    let (cmd, args) = match line.split_once(' ') {
+        Some((cmd, args)) => (cmd, args.trim()),
+        None => (line, ""),
+    };
+
+    match (cmd.to_ascii_uppercase().as_str(), args.is_empty()) {
+        ("LIST", true) => Ok(AdminCommand::List),
+        ("RELOAD", true) => Ok(AdminCommand::Reload),
+        ("STATS", true) => Ok(AdminCommand::Stats),
+        ("PAIRSTATS", true) => Ok(AdminCommand::PairStats(None)),
+        ("PAIRSTATS", false) if !args.contains(' ') => {
+            Ok(AdminCommand::PairStats(Some(String::from(args))))
+        }
+        ("KICK", false) if !args.contains(' ') => Ok(AdminCommand::Kick(String::from(args))),
+        ("KICKKEY", false) if !args.contains(' ') => Ok(AdminCommand::KickKey(String::from(args))),
+        ("BROADCAST", false) => Ok(AdminCommand::Broadcast(String::from(args))),
+        ("BAN", false) => parse_ban(args),
+        ("UNBAN", false) if !args.contains(' ') => Ok(AdminCommand::Unban(String::from(args))),
+        (
+            "LIST" | "RELOAD" | "STATS" | "PAIRSTATS" | "KICK" | "KICKKEY" | "BROADCAST" | "BAN"
+            | "UNBAN",
+            _,
+        ) => Err(String::from("malformed arguments")),
+        _ => Err(format!("unknown command {}", cmd)),
+    }
+}
+
+fn parse_ban(args: &str) -> Result<AdminCommand, String> {
+    let mut split = args.splitn(3, ' ');
+    let key = split.next().unwrap_or_default();
+    let hours = match split.next() {
+        Some("permanent") => None,
+        Some(hours) => Some(
+            hours
+                .parse::<u64>()
+                .map_err(|_| String::from("malformed arguments"))?,
+        ),
+        None => return Err(String::from("malformed arguments")),
+    };
+    let reason = split
+        .next()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .map(String::from);
+
+    Ok(AdminCommand::Ban(String::from(key), hours, reason))
+}
+
+/// Binds the admin socket, replacing a stale socket file, and restricts it to the current user.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+    }
+
+    // bound inside a private directory and moved into place once only the current user can
+    // access it, other users never get a window to connect
+    let dir = path.with_file_name(format!(".boop-admin-{}", std::process::id()));
+    // left behind by a crashed relay that had the same PID
+    if fs::symlink_metadata(&dir).is_ok() {
+        fs::remove_dir_all(&dir)?;
+    }
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp_path = dir.join("s");
+    let result = UnixListener::bind(&tmp_path).and_then(|listener| {
+        fs::set_permissions(&tmp_path, Permissions::from_mode(0o600))?;
+        fs::rename(&tmp_path, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&dir);
+
+    result
+}
+
+pub async fn serve(listener: UnixListener, relay: Arc<Relay>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                error!("admin socket error: {}", err);
+                continue;
+            }
+        };
+
+        let relay = Arc::clone(&relay);
+
+        tokio::spawn(async move {
+            if let Err(err) = handle_admin_connection(stream, &relay).await {
+                warn!("admin connection error: {}", err);
+            }
+        });
+    }
+}
+
+async fn handle_admin_connection(stream: UnixStream, relay: &Relay) -> io::Result<()> {
+    let (readhalf, mut writehalf) = stream.into_split();
+    let mut reader = BufReader::new(readhalf);
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+
+        let response = match parse_command(&line) {
+            Ok(cmd) => {
+                info!("admin command: {}", line.trim());
+                execute(cmd, relay).await
+            }
+            Err(reason) => Err(reason),
+        };
+
+        let text = match response {
+            Ok(lines) => {
+                lines
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect::<String>()
+                    + "OK\n"
+            }
+            Err(reason) => format!("ERROR {}\n", reason),
+        };
+        writehalf.write_all(text.as_bytes()).await?;
+    }
+}
+
+async fn execute(cmd: AdminCommand, relay: &Relay) -> Result<Vec<String>, String> {
+    let registry = &relay.registry;
+    let stats = &relay.stats;
+
+    match cmd {
+        AdminCommand::List => {
+            let mut lines: Vec<String> = registry
+                .connections()
+                .into_iter()
+                .map(|(key, connection_id, device)| match device {
+                    Some(device) => format!("{} {} {}", key, connection_id, device),
+                    None => format!("{} {}", key, connection_id),
+                })
+                .collect();
+            lines.sort();
+
+            Ok(lines)
+        }
+        AdminCommand::Kick(connection_id) => {
+            if registry.kick_connection(&connection_id) {
+                Stats::increment(&stats.kicked_connections);
+                Ok(Vec::new())
+            } else {
+                Err(format!("no connection {}", connection_id))
+            }
+        }
+        AdminCommand::KickKey(key) => {
+            let kicked = registry.kick_key(&key);
+            Stats::add(&stats.kicked_connections, kicked as u64);
+
+            Ok(vec![format!("kicked {}", kicked)])
+        }
+        AdminCommand::Broadcast(text) => {
+            let sent = registry.broadcast(&MessageType::NOTICE(text));
+
+            Ok(vec![format!("sent {}", sent)])
+        }
+        AdminCommand::Ban(key, hours, reason) => {
+            let until = match hours {
+                Some(hours) => Some(
+                    hours
+                        .checked_mul(60 * 60)
+                        .and_then(|seconds| unix_time().checked_add(seconds))
+                        .ok_or_else(|| format!("ban of {} hours is too long", hours))?,
+                ),
+                None => None,
+            };
+            let ban = Ban { until, reason };
+
+            match relay.clients.set_ban(&key, Some(ban.clone())).await {
+                Ok(true) => {
+                    info!("banned: {}", &key);
+                    let kicked = close_banned_key(registry, stats, &key, ban);
+                    let cancelled = match relay.scheduled.cancel_all(&key).await {
+                        Ok(cancelled) => cancelled,
+                        Err(err) => {
+                            // not delivered anyway while the ban is active
+                            error!("failed to cancel the scheduled boops of {}: {}", key, err);
+                            0
+                        }
+                    };
+                    Ok(vec![
+                        format!("kicked {}", kicked),
+                        format!("cancelled {}", cancelled),
+                    ])
+                }
+                Ok(false) => Err(format!("no client {}", key)),
+                Err(err) => Err(format!("saving clients failed: {}", err)),
+            }
+        }
+        AdminCommand::Unban(key) => match relay.clients.set_ban(&key, None).await {
+            Ok(true) => {
+                info!("unbanned: {}", &key);
+                Ok(Vec::new())
+            }
+            Ok(false) => Err(format!("no client {}", key)),
+            Err(err) => Err(format!("saving clients failed: {}", err)),
+        },
+        AdminCommand::Reload => {
+            let clients = relay
+                .clients
+                .reload()
+                .await
+                .map_err(|err| format!("reloading clients failed: {}", err))?;
+            info!("{} client entries read", clients);
+
+            let mut kicked = 0;
+            for key in relay.clients.banned_keys().await {
+                if let Some(ban) = relay.clients.active_ban(&key).await {
+                    kicked += close_banned_key(registry, stats, &key, ban);
+                }
+            }
+
+            let motd = relay
+                .motd
+                .reload()
+                .await
+                .map_err(|err| format!("reloading motd failed: {}", err))?;
+
+            Ok(vec![
+                format!("clients {}", clients),
+                format!("motd {}", motd),
+                format!("kicked {}", kicked),
+            ])
+        }
+        AdminCommand::Stats => {
+            let mut lines: Vec<String> = stats
+                .snapshot()
+                .into_iter()
+                .map(|(name, value)| format!("{} {}", name, value))
+                .collect();
+
+            let (online_keys, connections) = registry.counts();
+            lines.push(format!("online_keys {}", online_keys));
+            lines.push(format!("connections {}", connections));
+            lines.push(format!("open_connections {}", relay.connections.count()));
+
+            Ok(lines)
+        }
+        AdminCommand::PairStats(key) => Ok(relay
+            .pair_stats
+            .all(unix_time())
+            .await
+            .into_iter()
+            .filter(|counts| match &key {
+                Some(key) => &counts.from == key || &counts.to == key,
+                None => true,
+            })
+            .map(|counts| {
+                format!(
+                    "{} {} {} {} {} {}",
+                    counts.from,
+                    counts.to,
+                    counts.total,
+                    counts.today,
+                    counts.streak,
+                    counts.longest_streak
+                )
+            })
+            .collect()),
+    }
+}
+
+/// Tells all connections of a banned key about the ban, then closes them.
+fn close_banned_key(registry: &Registry, stats: &Stats, key: &str, ban: Ban) -> usize {
+    registry.fan_out(key, &MessageType::BANNED(ban.reason));
+    let kicked = registry.kick_key(key);
+    Stats::add(&stats.kicked_connections, kicked as u64);
+
+    kicked
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::{bind, parse_command, AdminCommand};
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[tokio::test]
+    async fn test_admin_bind() {
+        let dir = std::env::temp_dir().join(format!("boop-admin-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("admin.sock");
+
+        // replaces a stale socket, but nothing else
+        drop(bind(&path).unwrap());
+        let _listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // a stale temporary directory doesn't get in the way
+        let stale = dir.join(format!(".boop-admin-{}", std::process::id()));
+        fs::create_dir_all(stale.join("s")).unwrap();
+        drop(bind(&path).unwrap());
+        assert!(!stale.exists());
+
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        assert!(bind(&file).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_admin_parser_correct() {
+        assert_eq!(parse_command("LIST\n"), Ok(AdminCommand::List));
+        assert_eq!(parse_command("stats"), Ok(AdminCommand::Stats));
+        assert_eq!(parse_command("RELOAD\n"), Ok(AdminCommand::Reload));
+        assert_eq!(
+            parse_command("PAIRSTATS\n"),
+            Ok(AdminCommand::PairStats(None))
+        );
+        assert_eq!(
+            parse_command("pairstats foo\n"),
+            Ok(AdminCommand::PairStats(Some(String::from("foo"))))
+        );
+        assert_eq!(
+            parse_command("KICK 1234\n"),
+            Ok(AdminCommand::Kick(String::from("1234")))
+        );
+        assert_eq!(
+            parse_command("kickkey foo\n"),
+            Ok(AdminCommand::KickKey(String::from("foo")))
+        );
+        assert_eq!(
+            parse_command("BROADCAST maintenance at 10 pm\n"),
+            Ok(AdminCommand::Broadcast(String::from(
+                "maintenance at 10 pm"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_admin_parser_ban() {
+        assert_eq!(
+            parse_command("BAN foo permanent\n"),
+            Ok(AdminCommand::Ban(String::from("foo"), None, None))
+        );
+        assert_eq!(
+            parse_command("BAN foo 24 boop spam\n"),
+            Ok(AdminCommand::Ban(
+                String::from("foo"),
+                Some(24),
+                Some(String::from("boop spam"))
+            ))
+        );
+        assert_eq!(
+            parse_command("UNBAN foo\n"),
+            Ok(AdminCommand::Unban(String::from("foo")))
+        );
+
+        assert!(parse_command("BAN foo\n").is_err());
+        assert!(parse_command("BAN foo soon\n").is_err());
+        assert!(parse_command("UNBAN\n").is_err());
+    }
+
+    #[test]
+    fn test_admin_parser_incorrect() {
+        assert!(parse_command("DOESNOTEXIST\n").is_err());
+        assert!(parse_command("LIST foo\n").is_err());
+        assert!(parse_command("KICK\n").is_err());
+        assert!(parse_command("PAIRSTATS foo bar\n").is_err());
+        assert!(parse_command("KICKKEY foo bar\n").is_err());
+        assert!(parse_command("BROADCAST \n").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use argh::FromArgs;
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use boop_relay::{
+    client::{Client, ClientBuilder, ClientError, Event, Events},
+    message::error_text,
+};
+use serde_json::json;
+
+const PASSWORD_ENV: &str = "BOOP_PASSWORD";
+/// How long `ayt` waits for the answer.
+const AYT_TIMEOUT_SECS: u64 = 10;
+/// How long `boop` waits for an error after sending, the relay doesn't confirm boops.
+const BOOP_ERROR_WINDOW_MS: u64 = 500;
+
+#[derive(FromArgs, Debug)]
+/// Command-line client for a BOOP relay
+struct Options {
+    /// relay address with port
+    #[argh(positional)]
+    addr: String,
+
+    /// your key
+    #[argh(positional)]
+    key: String,
+
+    /// your password (default: the BOOP_PASSWORD environment variable)
+    #[argh(option, short = 'p')]
+    password: Option<String>,
+
+    /// PEM file with the certificates to trust instead of the webpki roots, e.g. for
+    /// self-signed test relays
+    #[argh(option)]
+    ca: Option<PathBuf>,
+
+    /// name checked against the relay's certificate (default: host part of the address)
+    #[argh(option)]
+    server_name: Option<String>,
+
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum Command {
+    Boop(BoopOptions),
+    BoopBack(BoopBackOptions),
+    Ayt(AytOptions),
+    Listen(ListenOptions),
+}
+
+#[derive(FromArgs, Debug)]
+/// Boop a partner once (exit code 1 if the relay rejects the boop)
+#[argh(subcommand, name = "boop")]
+struct BoopOptions {
+    /// key of the partner
+    #[argh(positional)]
+    partner: String,
+}
+
+#[derive(FromArgs, Debug)]
+/// Answer a boop by its ID (shown by listen)
+#[argh(subcommand, name = "boop-back")]
+struct BoopBackOptions {
+    /// ID of the boop
+    #[argh(positional)]
+    id: u64,
+}
+
+#[derive(FromArgs, Debug)]
+/// Check whether a partner is online (exit code 0 if online, 1 if afk, 2 on errors or
+/// timeouts)
+#[argh(subcommand, name = "ayt")]
+struct AytOptions {
+    /// key of the partner
+    #[argh(positional)]
+    partner: String,
+}
+
+#[derive(FromArgs, Debug)]
+/// Stay connected and print incoming events until ctrl-c
+#[argh(subcommand, name = "listen")]
+struct ListenOptions {
+    /// print events as JSON lines
+    #[argh(switch)]
+    json: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options: Options = argh::from_env();
+
+    match run(options).await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+async fn run(options: Options) -> Result<ExitCode, ClientError> {
+    let password = match options
+        .password
+        .or_else(|| std::env::var(PASSWORD_ENV).ok())
+    {
+        Some(password) => password,
+        None => {
+            eprintln!("error: no password, pass -p or set {}", PASSWORD_ENV);
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    let mut builder = ClientBuilder::new(&options.addr, &options.key, &password);
+    if let Some(ca) = &options.ca {
+        builder = builder.ca_file(ca)?;
+    }
+    if let Some(server_name) = &options.server_name {
+        builder = builder.server_name(server_name);
+    }
+    let (client, mut events) = builder.connect().await?;
+
+    match options.command {
+        Command::Boop(boop_options) => {
+            client.boop(&boop_options.partner).await?;
+            let code = check_errors(&mut events).await;
+            client.disconnect().await?;
+            Ok(code)
+        }
+        Command::BoopBack(boop_back_options) => {
+            client.boop_back(boop_back_options.id).await?;
+            let code = check_errors(&mut events).await;
+            client.disconnect().await?;
+            Ok(code)
+        }
+        Command::Ayt(ayt_options) => {
+            let ayt = client.ayt(&ayt_options.partner);
+            let online =
+                match tokio::time::timeout(Duration::from_secs(AYT_TIMEOUT_SECS), ayt).await {
+                    Ok(online) => online?,
+                    Err(_) => {
+                        eprintln!("error: no answer from the relay");
+                        return Ok(ExitCode::from(2));
+                    }
+                };
+            client.disconnect().await?;
+
+            println!("{}", if online { "online" } else { "afk" });
+            Ok(if online {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
+        }
+        Command::Listen(listen_options) => listen(client, events, listen_options.json).await,
+    }
+}
+
+/// Waits briefly for the relay to reject the last command, it only answers boops on errors.
+async fn check_errors(events: &mut Events) -> ExitCode {
+    let window = tokio::time::sleep(Duration::from_millis(BOOP_ERROR_WINDOW_MS));
+    tokio::pin!(window);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(Event::Error(kind)) => {
+                    eprintln!("error: {}", error_text(kind));
+                    return ExitCode::FAILURE;
+                }
+                Some(event @ Event::Deferred { .. }) => println!("{}", event_text(&event)),
+                Some(_) => {}
+                None => {
+                    eprintln!("error: the relay closed the connection, the account may be banned");
+                    return ExitCode::FAILURE;
+                }
+            },
+            _ = &mut window => return ExitCode::SUCCESS,
+        }
+    }
+}
+
+async fn listen(client: Client, mut events: Events, json: bool) -> Result<ExitCode, ClientError> {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => println!("{}", if json { event_json(&event) } else { event_text(&event) }),
+                None => {
+                    eprintln!("error: the relay refused to log in again");
+                    return Ok(ExitCode::FAILURE);
+                }
+            },
+            _ = tokio::signal::ctrl_c() => {
+                client.disconnect().await?;
+                return Ok(ExitCode::SUCCESS);
+            }
+        }
+    }
+}
+
+fn event_text(event: &Event) -> String {
+    match event {
+        Event::Boop {
+            from,
+            count: 1,
+            id: Some(id),
+        } => format!("boop from {} (id {})", from, id),
+        Event::Boop { from, count: 1, .. } => format!("boop from {}", from),
+        Event::Boop { from, count, .. } => format!("{} boops from {}", count, from),
+        Event::BoopBack { from, id } => format!("boop-back from {} to boop {}", from, id),
+        Event::Presence {
+            key, online: true, ..
+        } => format!("{} is online", key),
+        Event::Presence {
+            key,
+            online: false,
+            last_seen: Some(last_seen),
+        } => format!("{} is afk, last seen {}", key, time_text(*last_seen)),
+        Event::Presence {
+            key, online: false, ..
+        } => format!("{} is afk", key),
+        Event::Status {
+            key,
+            state,
+            text: Some(text),
+        } => format!("{} is {}: {}", key, state.text(), text),
+        Event::Status {
+            key,
+            state,
+            text: None,
+        } => format!("{} is {}", key, state.text()),
+        Event::Deferred { key } => format!("{} is in do-not-disturb mode, boop deferred", key),
+        Event::Notice(text) => format!("notice: {}", text),
+        Event::Error(kind) => format!("error: {}", error_text(kind.clone())),
+        Event::Disconnected => String::from("disconnected, reconnecting"),
+        Event::Reconnected => String::from("reconnected"),
+    }
+}
+
+fn time_text(timestamp: u64) -> String {
+    match chrono::DateTime::from_timestamp(timestamp as i64, 0) {
+        Some(time) => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+fn event_json(event: &Event) -> String {
+    let value = match event {
+        Event::Boop { from, count, id } => {
+            json!({ "event": "boop", "from": from, "count": count, "id": id })
+        }
+        Event::BoopBack { from, id } => json!({ "event": "boop_back", "from": from, "id": id }),
+        Event::Presence {
+            key,
+            online,
+            last_seen,
+        } => {
+            json!({ "event": "presence", "key": key, "online": online, "last_seen": last_seen })
+        }
+        Event::Status { key, state, text } => {
+            json!({ "event": "status", "key": key, "state": state.text(), "text": text })
+        }
+        Event::Deferred { key } => json!({ "event": "deferred", "key": key }),
+        Event::Notice(text) => json!({ "event": "notice", "text": text }),
+        Event::Error(kind) => json!({ "event": "error", "kind": error_text(kind.clone()) }),
+        Event::Disconnected => json!({ "event": "disconnected" }),
+        Event::Reconnected => json!({ "event": "reconnected" }),
+    };
+
+    value.to_string()
+}
@@ -0,0 +1,713 @@
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    fmt,
+    fs::File,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_core::Stream;
+use rustls_pemfile::certs;
+use tokio::{
+    io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+    time::{self, Instant},
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{self, Certificate, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+use crate::message::{
+    create_message_text, parse_message, LoginOptions, MessageErrorKind, MessageType, PresenceState,
+};
+
+/// Well below the relay's default keepalive timeout of 60 seconds.
+const PING_INTERVAL_SECS: u64 = 10;
+/// The connection is considered dead after this many pings without any message from the relay.
+const MISSED_PINGS: u32 = 3;
+const INITIAL_BACKOFF_SECS: u64 = 1;
+const MAX_BACKOFF_SECS: u64 = 60;
+const BYE_TIMEOUT_SECS: u64 = 5;
+
+/// Something that happened on the connection to the relay.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Someone booped us, `count` > 1 if the relay merged several boops.
+    Boop {
+        from: String,
+        count: u32,
+        /// ID to answer the boop with [`Client::boop_back`], not set for merged boops.
+        id: Option<u64>,
+    },
+    /// Someone answered our boop with the ID.
+    BoopBack {
+        from: String,
+        id: u64,
+    },
+    /// Answer to a presence check.
+    Presence {
+        key: String,
+        online: bool,
+        /// Unix timestamp of the key's last disconnect, if it's offline and shares it.
+        last_seen: Option<u64>,
+    },
+    /// Presence state and status text of an online key, follows `Presence`.
+    Status {
+        key: String,
+        state: PresenceState,
+        text: Option<String>,
+    },
+    /// A boop to `key` is held back until its do-not-disturb mode ends.
+    Deferred {
+        key: String,
+    },
+    Notice(String),
+    /// The relay refused a command, e.g. because of rate limits.
+    Error(MessageErrorKind),
+    /// The connection was lost, the client keeps trying to reconnect.
+    Disconnected,
+    /// Logged in again after the connection was lost.
+    Reconnected,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// Wrong key or password.
+    LoginRefused,
+    /// The account is banned, with an optional reason.
+    Banned(Option<String>),
+    /// The relay answered the login with an error.
+    Relay(MessageErrorKind),
+    /// The relay answered the login with something other than `HEY`.
+    UnexpectedMessage(MessageType),
+    /// The client was disconnected before the command was answered.
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "{}", err),
+            ClientError::LoginRefused => write!(f, "login refused"),
+            ClientError::Banned(Some(reason)) => write!(f, "account is banned: {}", reason),
+            ClientError::Banned(None) => write!(f, "account is banned"),
+            ClientError::Relay(kind) => write!(f, "relay error: {:?}", kind),
+            ClientError::UnexpectedMessage(msg) => write!(f, "unexpected message: {:?}", msg),
+            ClientError::Closed => write!(f, "client is disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        ClientError::Io(err)
+    }
+}
+
+/// Configures and connects a [`Client`].
+///
+/// ```no_run
+/// # async fn run() -> Result<(), boop_relay::client::ClientError> {
+/// use boop_relay::client::{ClientBuilder, Event};
+///
+/// let (client, mut events) = ClientBuilder::new("boop.example.com:6969", "foo", "bar")
+///     .connect()
+///     .await?;
+/// client.boop("foo2").await?;
+/// while let Some(event) = events.recv().await {
+///     if let Event::Boop { from, .. } = event {
+///         println!("booped by {}", from);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClientBuilder {
+    addr: String,
+    server_name: Option<String>,
+    key: String,
+    password: String,
+    tls: Option<Arc<rustls::ClientConfig>>,
+    device: Option<String>,
+    ping_interval: Duration,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl ClientBuilder {
+    pub fn new(addr: &str, key: &str, password: &str) -> ClientBuilder {
+        ClientBuilder {
+            addr: String::from(addr),
+            server_name: None,
+            key: String::from(key),
+            password: String::from(password),
+            tls: None,
+            device: None,
+            ping_interval: Duration::from_secs(PING_INTERVAL_SECS),
+            initial_backoff: Duration::from_secs(INITIAL_BACKOFF_SECS),
+            max_backoff: Duration::from_secs(MAX_BACKOFF_SECS),
+        }
+    }
+
+    /// Name checked against the relay's certificate (default: host part of the address).
+    pub fn server_name(mut self, name: &str) -> ClientBuilder {
+        self.server_name = Some(String::from(name));
+        self
+    }
+
+    /// Ready-made TLS config (default: the webpki root certificates).
+    pub fn tls_config(mut self, config: Arc<rustls::ClientConfig>) -> ClientBuilder {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Trusts only the certificates in the PEM file, e.g. a self-signed relay certificate.
+    pub fn ca_file(self, path: &Path) -> io::Result<ClientBuilder> {
+        let mut roots = RootCertStore::empty();
+        let ca_certs = certs(&mut std::io::BufReader::new(File::open(path)?))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))?;
+        for cert in ca_certs {
+            roots
+                .add(&Certificate(cert))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        }
+
+        Ok(self.tls_config(tls_config_with_roots(roots)))
+    }
+
+    /// Name of this device, lets partners boop it with `<key>/<device>`.
+    pub fn device(mut self, name: &str) -> ClientBuilder {
+        self.device = Some(String::from(name));
+        self
+    }
+
+    pub fn ping_interval(mut self, interval: Duration) -> ClientBuilder {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// Delay before the first reconnect attempt, doubled after every failed attempt up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> ClientBuilder {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Connects and logs in. Fails if the first login doesn't succeed; afterwards the
+    /// client reconnects on its own until it is disconnected or the login is refused.
+    pub async fn connect(self) -> Result<(Client, Events), ClientError> {
+        let host = self.server_name.clone().unwrap_or_else(|| host(&self.addr));
+        let server_name = ServerName::try_from(host.as_str())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let tls = self.tls.clone().unwrap_or_else(|| {
+            let mut roots = RootCertStore::empty();
+            roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+            tls_config_with_roots(roots)
+        });
+
+        let settings = Settings {
+            addr: self.addr,
+            server_name,
+            connector: TlsConnector::from(tls),
+            key: self.key,
+            password: self.password,
+            device: self.device,
+            ping_interval: self.ping_interval,
+            initial_backoff: self.initial_backoff,
+            max_backoff: self.max_backoff,
+        };
+
+        let connection = login(&settings).await?;
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let (events_tx, events) = mpsc::unbounded_channel();
+        tokio::spawn(run(settings, connection, commands_rx, events_tx));
+
+        Ok((Client { commands }, Events { rx: events }))
+    }
+}
+
+fn tls_config_with_roots(roots: RootCertStore) -> Arc<rustls::ClientConfig> {
+    Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+}
+
+/// Host part of `host:port` or `[ipv6]:port`.
+fn host(addr: &str) -> String {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    String::from(host.trim_start_matches('[').trim_end_matches(']'))
+}
+
+enum Command {
+    Boop(String),
+    BoopBack(u64),
+    Ayt(String, PresenceReply),
+    Status(PresenceState, Option<String>),
+    Disconnect(oneshot::Sender<()>),
+}
+
+/// Waits for the answer to an `AYT` or `DEVICES` check.
+enum PresenceReply {
+    Online(oneshot::Sender<Result<bool, ClientError>>),
+    Devices(oneshot::Sender<Result<Option<Vec<String>>, ClientError>>),
+}
+
+/// Handle to a logged in connection. Clones share the same connection, which is closed
+/// once every handle is dropped.
+#[derive(Clone)]
+pub struct Client {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Client {
+    /// Boops a partner. Boops sent while reconnecting are delivered after the next login.
+    pub async fn boop(&self, partner_key: &str) -> Result<(), ClientError> {
+        self.commands
+            .send(Command::Boop(String::from(partner_key)))
+            .map_err(|_| ClientError::Closed)
+    }
+
+    /// Answers a boop with its ID, the boop-back reaches every connection of the booper.
+    pub async fn boop_back(&self, id: u64) -> Result<(), ClientError> {
+        self.commands
+            .send(Command::BoopBack(id))
+            .map_err(|_| ClientError::Closed)
+    }
+
+    /// Checks whether a partner is online. Fails with [`ClientError::Relay`] if the relay
+    /// rejects the check, e.g. because of rate limits.
+    pub async fn ayt(&self, partner_key: &str) -> Result<bool, ClientError> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.commands
+            .send(Command::Ayt(
+                String::from(partner_key),
+                PresenceReply::Online(reply),
+            ))
+            .map_err(|_| ClientError::Closed)?;
+
+        reply_rx.await.map_err(|_| ClientError::Closed)?
+    }
+
+    /// Device names of a partner's connections, `None` if the partner is offline.
+    /// Connections without a device name are not listed.
+    pub async fn devices(&self, partner_key: &str) -> Result<Option<Vec<String>>, ClientError> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.commands
+            .send(Command::Ayt(
+                String::from(partner_key),
+                PresenceReply::Devices(reply),
+            ))
+            .map_err(|_| ClientError::Closed)?;
+
+        reply_rx.await.map_err(|_| ClientError::Closed)?
+    }
+
+    /// Sets the presence state and status text of this connection. The relay reports the
+    /// most available state of all connections of a key. The status is set again after
+    /// reconnecting.
+    pub async fn set_status(
+        &self,
+        state: PresenceState,
+        text: Option<&str>,
+    ) -> Result<(), ClientError> {
+        self.commands
+            .send(Command::Status(state, text.map(String::from)))
+            .map_err(|_| ClientError::Closed)
+    }
+
+    /// Says goodbye to the relay and stops reconnecting. The event stream ends afterwards.
+    pub async fn disconnect(&self) -> Result<(), ClientError> {
+        let (done, done_rx) = oneshot::channel();
+        if self.commands.send(Command::Disconnect(done)).is_ok() {
+            let _ = done_rx.await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stream of [`Event`]s, ends when the client is disconnected for good.
+pub struct Events {
+    rx: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Events {
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+struct Settings {
+    addr: String,
+    server_name: ServerName,
+    connector: TlsConnector,
+    key: String,
+    password: String,
+    device: Option<String>,
+    ping_interval: Duration,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+struct Connection {
+    reader: BufReader<ReadHalf<TlsStream<TcpStream>>>,
+    writer: WriteHalf<TlsStream<TcpStream>>,
+}
+
+async fn login(settings: &Settings) -> Result<Connection, ClientError> {
+    let stream = TcpStream::connect(&settings.addr).await?;
+    let stream = settings
+        .connector
+        .connect(settings.server_name.clone(), stream)
+        .await?;
+    let (reader, mut writer) = split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let connect = MessageType::CONNECT(
+        settings.key.clone(),
+        settings.password.clone(),
+        LoginOptions {
+            device: settings.device.clone(),
+            presence: true,
+            ids: true,
+            ..LoginOptions::default()
+        },
+    );
+    send_message(&mut writer, connect).await?;
+
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    match parse_message(&line) {
+        Ok(MessageType::HEY) => Ok(Connection { reader, writer }),
+        Ok(MessageType::NO) => Err(ClientError::LoginRefused),
+        Ok(MessageType::BANNED(reason)) => Err(ClientError::Banned(reason)),
+        Ok(MessageType::ERROR(kind)) => Err(ClientError::Relay(kind)),
+        Ok(msg) => Err(ClientError::UnexpectedMessage(msg)),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed message").into()),
+    }
+}
+
+async fn send_message(
+    writer: &mut WriteHalf<TlsStream<TcpStream>>,
+    message: MessageType,
+) -> io::Result<()> {
+    let msg_text = create_message_text(message);
+    writer.write_all(msg_text.as_bytes()).await
+}
+
+/// Keeps the client connected until it is disconnected or the login is refused.
+async fn run(
+    settings: Settings,
+    mut connection: Connection,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    events: mpsc::UnboundedSender<Event>,
+) {
+    // commands received while reconnecting
+    let mut queued = VecDeque::new();
+    // the last status set, restored after reconnecting
+    let mut status = None;
+
+    loop {
+        let result = session(
+            &settings,
+            connection,
+            &mut commands,
+            &mut queued,
+            &mut status,
+            &events,
+        )
+        .await;
+        match result {
+            Ok(()) => return,
+            Err(err) => {
+                info!("lost connection to the relay: {}", err);
+                let _ = events.send(Event::Disconnected);
+            }
+        }
+
+        connection = match reconnect(&settings, &mut commands, &mut queued).await {
+            Some(connection) => connection,
+            None => return,
+        };
+        if let Some((state, text)) = status.clone() {
+            queued.push_front(Command::Status(state, text));
+        }
+        let _ = events.send(Event::Reconnected);
+    }
+}
+
+async fn reconnect(
+    settings: &Settings,
+    commands: &mut mpsc::UnboundedReceiver<Command>,
+    queued: &mut VecDeque<Command>,
+) -> Option<Connection> {
+    let mut backoff = settings.initial_backoff;
+
+    loop {
+        let sleep = time::sleep(backoff);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                cmd = commands.recv() => match cmd {
+                    Some(Command::Disconnect(done)) => {
+                        let _ = done.send(());
+                        return None;
+                    },
+                    Some(cmd) => queued.push_back(cmd),
+                    None => return None,
+                }
+            }
+        }
+
+        match login(settings).await {
+            Ok(connection) => return Some(connection),
+            Err(err @ (ClientError::LoginRefused | ClientError::Banned(_))) => {
+                warn!("giving up reconnecting: {}", err);
+                return None;
+            }
+            Err(err) => debug!("reconnect failed: {}", err),
+        }
+
+        backoff = (backoff * 2).min(settings.max_backoff);
+    }
+}
+
+enum Flow {
+    Continue,
+    Disconnect(Option<oneshot::Sender<()>>),
+}
+
+/// Runs a logged in connection. Returns `Ok` once the client is disconnected on purpose.
+async fn session(
+    settings: &Settings,
+    connection: Connection,
+    commands: &mut mpsc::UnboundedReceiver<Command>,
+    queued: &mut VecDeque<Command>,
+    status: &mut Option<(PresenceState, Option<String>)>,
+    events: &mpsc::UnboundedSender<Event>,
+) -> io::Result<()> {
+    let Connection {
+        mut reader,
+        mut writer,
+    } = connection;
+    let mut pending_ayt = Vec::new();
+    let mut ping = time::interval_at(
+        Instant::now() + settings.ping_interval,
+        settings.ping_interval,
+    );
+    let mut last_received = Instant::now();
+    // kept across loop iterations, read_line may be cancelled halfway through a line
+    let mut line = String::new();
+
+    while let Some(cmd) = queued.pop_front() {
+        if let Flow::Disconnect(done) = execute(cmd, &mut writer, &mut pending_ayt, status).await? {
+            return close(reader, writer, done).await;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                if last_received.elapsed() > settings.ping_interval * MISSED_PINGS {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "relay stopped answering"));
+                }
+                send_message(&mut writer, MessageType::PING).await?;
+            },
+            res = reader.read_line(&mut line) => {
+                if res? == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+                last_received = Instant::now();
+
+                match parse_message(&line) {
+                    Ok(MessageType::PING) => send_message(&mut writer, MessageType::PONG).await?,
+                    Ok(msg) => handle_message(msg, &mut pending_ayt, events),
+                    Err(err) => debug!("malformed message from the relay ({:?}): {}", err, line.trim()),
+                }
+                line.clear();
+            },
+            cmd = commands.recv() => {
+                let flow = match cmd {
+                    Some(cmd) => execute(cmd, &mut writer, &mut pending_ayt, status).await?,
+                    None => Flow::Disconnect(None), // every handle was dropped
+                };
+                if let Flow::Disconnect(done) = flow {
+                    return close(reader, writer, done).await;
+                }
+            }
+        }
+    }
+}
+
+async fn execute(
+    cmd: Command,
+    writer: &mut WriteHalf<TlsStream<TcpStream>>,
+    pending_ayt: &mut Vec<(String, PresenceReply)>,
+    status: &mut Option<(PresenceState, Option<String>)>,
+) -> io::Result<Flow> {
+    match cmd {
+        Command::Boop(partner_key) => {
+            send_message(writer, MessageType::BOOP(partner_key, None)).await?;
+        }
+        Command::BoopBack(id) => {
+            send_message(writer, MessageType::BOOPBACK(None, id)).await?;
+        }
+        Command::Ayt(partner_key, reply) => {
+            let msg = match reply {
+                PresenceReply::Online(_) => MessageType::AYT(partner_key.clone()),
+                PresenceReply::Devices(_) => MessageType::DEVICES(partner_key.clone()),
+            };
+            send_message(writer, msg).await?;
+            pending_ayt.push((partner_key, reply));
+        }
+        Command::Status(state, text) => {
+            send_message(writer, MessageType::STATUS(state, text.clone())).await?;
+            *status = Some((state, text));
+        }
+        Command::Disconnect(done) => return Ok(Flow::Disconnect(Some(done))),
+    }
+
+    Ok(Flow::Continue)
+}
+
+fn handle_message(
+    msg: MessageType,
+    pending_ayt: &mut Vec<(String, PresenceReply)>,
+    events: &mpsc::UnboundedSender<Event>,
+) {
+    let event = match msg {
+        MessageType::BOOP(from, id) => Event::Boop { from, count: 1, id },
+        MessageType::BOOPS(from, count) => Event::Boop {
+            from,
+            count,
+            id: None,
+        },
+        MessageType::BOOPBACK(Some(from), id) => Event::BoopBack { from, id },
+        MessageType::ONLINE(key, devices) => presence(key, Some(devices), None, pending_ayt),
+        MessageType::AFK(key, last_seen) => presence(key, None, last_seen, pending_ayt),
+        MessageType::PRESENCE(key, state, text) => Event::Status { key, state, text },
+        MessageType::DEFERRED(key) => Event::Deferred { key },
+        MessageType::NOTICE(text) => Event::Notice(text),
+        MessageType::ERROR(kind) => {
+            // the relay answers commands in order, and only presence checks wait for an
+            // answer, so the error is assumed to belong to the oldest one
+            if !pending_ayt.is_empty() {
+                let error = ClientError::Relay(kind.clone());
+                let _ = match pending_ayt.remove(0).1 {
+                    PresenceReply::Online(reply) => reply.send(Err(error)).map_err(drop),
+                    PresenceReply::Devices(reply) => reply.send(Err(error)).map_err(drop),
+                };
+            }
+            Event::Error(kind)
+        }
+        MessageType::PONG => return,
+        msg => {
+            debug!("unexpected message from the relay: {:?}", msg);
+            return;
+        }
+    };
+
+    let _ = events.send(event);
+}
+
+/// Answers the oldest presence check for the key, `devices` is `None` if the key is offline.
+fn presence(
+    key: String,
+    devices: Option<Vec<String>>,
+    last_seen: Option<u64>,
+    pending_ayt: &mut Vec<(String, PresenceReply)>,
+) -> Event {
+    let online = devices.is_some();
+    if let Some(index) = pending_ayt.iter().position(|(pending, _)| *pending == key) {
+        let _ = match pending_ayt.remove(index).1 {
+            PresenceReply::Online(reply) => reply.send(Ok(online)).map_err(drop),
+            PresenceReply::Devices(reply) => reply.send(Ok(devices)).map_err(drop),
+        };
+    }
+
+    Event::Presence {
+        key,
+        online,
+        last_seen,
+    }
+}
+
+/// Says goodbye and waits (briefly) for the relay to confirm.
+async fn close(
+    mut reader: BufReader<ReadHalf<TlsStream<TcpStream>>>,
+    mut writer: WriteHalf<TlsStream<TcpStream>>,
+    done: Option<oneshot::Sender<()>>,
+) -> io::Result<()> {
+    if send_message(&mut writer, MessageType::DISCONNECT)
+        .await
+        .is_ok()
+    {
+        let _ = time::timeout(Duration::from_secs(BYE_TIMEOUT_SECS), async {
+            let mut line = String::new();
+            while let Ok(n) = reader.read_line(&mut line).await {
+                if n == 0 || parse_message(&line) == Ok(MessageType::BYE) {
+                    break;
+                }
+                line.clear();
+            }
+        })
+        .await;
+    }
+    let _ = writer.shutdown().await;
+
+    if let Some(done) = done {
+        let _ = done.send(());
+    }
+    Ok(())
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::host;
+
+    #[test]
+    fn test_client_host() {
+        assert_eq!(host("boop.example.com:6969"), "boop.example.com");
+        assert_eq!(host("127.0.0.1:6969"), "127.0.0.1");
+        assert_eq!(host("[::1]:6969"), "::1");
+        assert_eq!(host("localhost"), "localhost");
+    }
+}
@@ -1,41 +1,222 @@
-use std::{io::Error, path::PathBuf};
+use std::{
+    io::{self, Error},
+    path::{Path, PathBuf},
+};
 
 use argon2::{
-    password_hash::{PasswordHash, PasswordVerifier},
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 
-use serde::Deserialize;
-use tokio::fs;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::RwLock};
+
+use crate::unix_time;
+
+const MAX_KEY_LENGTH: usize = 32;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Client {
     pub key: String,
     pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ban: Option<Ban>,
+}
+
+/// A disabled account. The client entry is kept, but logins are refused while the ban is active.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ban {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>, // unix timestamp in seconds, permanent if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
-pub async fn read_clients_file(clients_config: &PathBuf) -> Result<Vec<Client>, Error> {
+impl Ban {
+    pub fn is_active(&self, now: u64) -> bool {
+        match self.until {
+            Some(until) => until > now,
+            None => true,
+        }
+    }
+}
+
+/// The list of known clients, backed by the clients config file.
+pub struct ClientStore {
+    path: PathBuf,
+    clients: RwLock<Vec<Client>>,
+}
+
+impl ClientStore {
+    pub async fn open(path: &Path) -> Result<ClientStore, Error> {
+        let clients = read_clients_file(path).await?;
+
+        Ok(ClientStore {
+            path: path.to_path_buf(),
+            clients: RwLock::new(clients),
+        })
+    }
+
+    /// Re-reads the clients config file. Returns the new number of clients.
+    pub async fn reload(&self) -> Result<usize, Error> {
+        let clients = read_clients_file(&self.path).await?;
+        let count = clients.len();
+        *self.clients.write().await = clients;
+
+        Ok(count)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.clients.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.clients.read().await.is_empty()
+    }
+
+    pub async fn contains(&self, key: &str) -> bool {
+        self.clients
+            .read()
+            .await
+            .iter()
+            .any(|client| client.key == key)
+    }
+
+    pub async fn login_is_valid(
+        &self,
+        key: &str,
+        password: &str,
+    ) -> Result<bool, argon2::password_hash::Error> {
+        client_login_is_valid(key, password, &self.clients.read().await)
+    }
+
+    /// Returns the ban of the client, if it is currently active.
+    pub async fn active_ban(&self, key: &str) -> Option<Ban> {
+        let now = unix_time();
+
+        self.clients
+            .read()
+            .await
+            .iter()
+            .find(|client| client.key == key)
+            .and_then(|client| client.ban.clone())
+            .filter(|ban| ban.is_active(now))
+    }
+
+    /// Keys of all clients with an active ban.
+    pub async fn banned_keys(&self) -> Vec<String> {
+        let now = unix_time();
+
+        self.clients
+            .read()
+            .await
+            .iter()
+            .filter(|client| client.ban.as_ref().is_some_and(|ban| ban.is_active(now)))
+            .map(|client| client.key.clone())
+            .collect()
+    }
+
+    /// Sets or lifts (`None`) the ban of a client and writes the updated list back to the
+    /// clients config file. Returns `false` if the key doesn't exist.
+    pub async fn set_ban(&self, key: &str, ban: Option<Ban>) -> Result<bool, Error> {
+        self.update(key, |client| client.ban = ban).await
+    }
+
+    /// Changes a client and writes the updated list back to the clients config file, or
+    /// restores the client if that fails. Returns `false` if the key doesn't exist.
+    async fn update(&self, key: &str, change: impl FnOnce(&mut Client)) -> Result<bool, Error> {
+        let mut clients = self.clients.write().await;
+
+        let index = match clients.iter().position(|client| client.key == key) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let previous = clients[index].clone();
+        change(&mut clients[index]);
+
+        if let Err(err) = write_clients_file(&self.path, &clients).await {
+            clients[index] = previous;
+            return Err(err);
+        }
+
+        Ok(true)
+    }
+
+    /// Adds a new client and writes the updated list back to the clients config file.
+    /// Returns `false` if the key is already taken.
+    pub async fn register(&self, key: &str, password: &str) -> Result<bool, Error> {
+        // hashing takes a while on purpose, keep it off the runtime's worker threads
+        let password = String::from(password);
+        let hash = tokio::task::spawn_blocking(move || hash_password(&password))
+            .await
+            .map_err(Error::other)?
+            .map_err(|err| Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+        let mut clients = self.clients.write().await;
+        if clients.iter().any(|client| client.key == key) {
+            return Ok(false);
+        }
+
+        clients.push(Client {
+            key: String::from(key),
+            hash,
+            ..Client::default()
+        });
+
+        if let Err(err) = write_clients_file(&self.path, &clients).await {
+            clients.pop();
+            return Err(err);
+        }
+
+        Ok(true)
+    }
+}
+
+pub async fn read_clients_file(clients_config: &Path) -> Result<Vec<Client>, Error> {
     let contents = fs::read_to_string(clients_config).await?;
-    let clients: Vec<Client> = serde_json::from_str(&contents.as_str())?;
+    let clients: Vec<Client> = serde_json::from_str(contents.as_str())?;
 
     Ok(clients)
 }
 
+pub async fn write_clients_file(clients_config: &Path, clients: &[Client]) -> Result<(), Error> {
+    let contents = serde_json::to_string_pretty(clients)?;
+
+    // write to a temporary file first so a crash never leaves a half-written clients file behind
+    let tmp_path = clients_config.with_extension("tmp");
+    fs::write(&tmp_path, contents).await?;
+    fs::rename(&tmp_path, clients_config).await
+}
+
+/// Keys are used as protocol arguments, so they must not contain whitespace or separators.
+pub fn key_is_valid(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_KEY_LENGTH
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+
+    Ok(hash.to_string())
+}
+
+/// Checks the password of a client. Fails if the stored hash of the client is invalid.
 pub fn client_login_is_valid(
-    key: &String,
-    password: &String,
-    clients: &Vec<Client>,
-) -> Result<bool, ()> {
+    key: &str,
+    password: &str,
+    clients: &[Client],
+) -> Result<bool, argon2::password_hash::Error> {
     let mut client_iter = clients.iter();
 
-    if let Some(client) = client_iter.find(|client| &client.key == key) {
-        if let Ok(parsed_hash) = PasswordHash::new(&client.hash) {
-            Ok(Argon2::default()
-                .verify_password(password.as_bytes(), &parsed_hash)
-                .is_ok())
-        } else {
-            Err(())
-        }
+    if let Some(client) = client_iter.find(|client| client.key == key) {
+        let parsed_hash = PasswordHash::new(&client.hash)?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok())
     } else {
         Ok(false)
     }
@@ -49,7 +230,7 @@ pub fn client_login_is_valid(
 
 #[cfg(test)]
 mod tests {
-    use super::{client_login_is_valid, Client};
+    use super::{client_login_is_valid, hash_password, key_is_valid, Ban, Client};
 
     #[test]
     fn test_hash_validation_correct() {
@@ -59,12 +240,14 @@ mod tests {
                 hash: String::from(
                     "$argon2id$v=19$m=32,t=2,p=1$V3hudnFvVEJwTnFjNGRMVA$E+sVHTGn3oMAFHhk27r05A",
                 ),
+                ..Client::default()
             },
             Client {
                 key: String::from("iyoshok"),
                 hash: String::from(
                     "$argon2id$v=19$m=16,t=2,p=1$bGVWbjBzNEFxZTZLSkh2MA$Z1pgP1acelPKkL2nny9XsA",
                 ),
+                ..Client::default()
             },
         ];
 
@@ -81,12 +264,14 @@ mod tests {
                 hash: String::from(
                     "$argon2id$v=19$m=32,t=2,p=1$V3hudnFvVEJwTnFjNGRMVA$E+sVHTGn3oMAFHhk27r05A",
                 ),
+                ..Client::default()
             },
             Client {
                 key: String::from("iyoshok"),
                 hash: String::from(
                     "$argon2id$v=19$m=16,t=2,p=1$bGVWbjBzNEFxZTZLSkh2MA$Z1pgP1acelPKkL2nny9XsA",
                 ),
+                ..Client::default()
             },
         ];
 
@@ -98,4 +283,73 @@ mod tests {
         assert!(test_res.is_ok());
         assert!(!test_res.unwrap());
     }
+
+    #[test]
+    fn test_hash_password_roundtrip() {
+        let clients = vec![Client {
+            key: String::from("foo"),
+            hash: hash_password("bar").unwrap(),
+            ..Client::default()
+        }];
+
+        assert_eq!(client_login_is_valid("foo", "bar", &clients), Ok(true));
+        assert_eq!(client_login_is_valid("foo", "baz", &clients), Ok(false));
+    }
+
+    #[test]
+    fn test_key_validation() {
+        assert!(key_is_valid("foo"));
+        assert!(key_is_valid("foo-bar_2"));
+
+        assert!(!key_is_valid(""));
+        assert!(!key_is_valid("foo bar"));
+        assert!(!key_is_valid("foo/bar"));
+        assert!(!key_is_valid("fööbar"));
+        assert!(!key_is_valid(&"a".repeat(33)));
+    }
+
+    #[test]
+    fn test_ban_expiry() {
+        let permanent = Ban {
+            until: None,
+            reason: None,
+        };
+        assert!(permanent.is_active(0));
+        assert!(permanent.is_active(u64::MAX));
+
+        let temporary = Ban {
+            until: Some(1000),
+            reason: Some(String::from("spam")),
+        };
+        assert!(temporary.is_active(999));
+        assert!(!temporary.is_active(1000));
+    }
+
+    #[test]
+    fn test_ban_deserialization() {
+        let clients: Vec<Client> = serde_json::from_str(
+            r#"[
+                { "key": "foo", "hash": "x" },
+                { "key": "bar", "hash": "x", "ban": { "until": 1000, "reason": "spam" } },
+                { "key": "baz", "hash": "x", "ban": {} }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(clients[0].ban, None);
+        assert_eq!(
+            clients[1].ban,
+            Some(Ban {
+                until: Some(1000),
+                reason: Some(String::from("spam"))
+            })
+        );
+        assert_eq!(
+            clients[2].ban,
+            Some(Ban {
+                until: None,
+                reason: None
+            })
+        );
+    }
 }
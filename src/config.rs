@@ -0,0 +1,93 @@
+use std::{io::Error, path::Path};
+
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::{
+    connlimit::ConnectionLimitConfig, dnd::DndConfig, history::HistoryConfig,
+    keepalive::KeepaliveConfig, outbox::QueueConfig, proxy::ProxyConfig,
+    ratelimit::RateLimitConfig, schedule::ScheduleConfig, webhooks::WebhookConfig,
+};
+
+/// Optional relay settings. Every field has a default, so the config file only needs to
+/// contain the settings that differ.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    pub rate_limits: RateLimitConfig,
+    pub queue: QueueConfig,
+    pub proxy_protocol: ProxyConfig,
+    pub connection_limits: ConnectionLimitConfig,
+    pub keepalive: KeepaliveConfig,
+    pub history: HistoryConfig,
+    pub scheduled: ScheduleConfig,
+    pub dnd: DndConfig,
+    pub webhooks: WebhookConfig,
+}
+
+pub async fn read_config_file(path: &Path) -> Result<RelayConfig, Error> {
+    let contents = fs::read_to_string(path).await?;
+    let config: RelayConfig = serde_json::from_str(contents.as_str())?;
+
+    Ok(config)
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::RelayConfig;
+    use crate::ratelimit::Limit;
+
+    #[test]
+    fn test_config_defaults() {
+        let config: RelayConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, RelayConfig::default());
+
+        let config: RelayConfig = serde_json::from_str(
+            r#"{ "rate_limits": { "connection": { "boop": { "rate": 0.5, "burst": 3 } } } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.rate_limits.connection.boop,
+            Some(Limit {
+                rate: 0.5,
+                burst: 3
+            })
+        );
+        assert_eq!(
+            config.rate_limits.key,
+            RelayConfig::default().rate_limits.key
+        );
+    }
+
+    #[test]
+    fn test_config_invalid_limits() {
+        for limit in [
+            r#"{ "rate": 0, "burst": 3 }"#,
+            r#"{ "rate": -1.5, "burst": 3 }"#,
+            r#"{ "rate": 1e999, "burst": 3 }"#,
+            r#"{ "rate": 1, "burst": 0 }"#,
+            r#"{ "rate": 1, "burst": -3 }"#,
+        ] {
+            let config = format!(
+                r#"{{ "rate_limits": {{ "key": {{ "ayt": {} }} }} }}"#,
+                limit
+            );
+            assert!(
+                serde_json::from_str::<RelayConfig>(&config).is_err(),
+                "{}",
+                limit
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_unknown_field() {
+        assert!(serde_json::from_str::<RelayConfig>(r#"{ "rate_limit": {} }"#).is_err());
+    }
+}
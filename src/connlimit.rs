@@ -0,0 +1,214 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use serde::Deserialize;
+
+/// Caps on concurrent connections. `None` means unlimited.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimitConfig {
+    /// Connections of the whole relay, logged in or not.
+    pub total: Option<usize>,
+    /// Connections from a single IP address (the client address for proxied connections).
+    pub per_ip: Option<usize>,
+    /// Logged in connections of a single key.
+    pub sessions_per_key: Option<usize>,
+    /// Close the oldest connection of a key instead of refusing the login when the key is
+    /// at `sessions_per_key`.
+    pub evict_oldest: bool,
+}
+
+/// Why a connection was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refusal {
+    Total,
+    PerIp,
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Counts open connections and refuses new ones over the configured limits.
+pub struct ConnectionTracker {
+    config: ConnectionLimitConfig,
+    counts: Mutex<Counts>,
+}
+
+/// An admitted connection, counted until it is dropped.
+pub struct ConnectionPermit<'a> {
+    tracker: &'a ConnectionTracker,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionTracker {
+    pub fn new(config: ConnectionLimitConfig) -> ConnectionTracker {
+        ConnectionTracker {
+            config,
+            counts: Mutex::new(Counts::default()),
+        }
+    }
+
+    /// Counts a new connection from the address (`None` for unix sockets), unless that
+    /// would exceed a limit.
+    pub fn admit(&self, ip: Option<IpAddr>) -> Result<ConnectionPermit<'_>, Refusal> {
+        // IPv4 clients of dual-stack listeners show up as IPv4-mapped IPv6 addresses
+        let ip = ip.map(|ip| ip.to_canonical());
+        let mut counts = self.counts.lock().unwrap();
+
+        if self.config.total.is_some_and(|max| counts.total >= max) {
+            return Err(Refusal::Total);
+        }
+        if let (Some(ip), Some(max)) = (ip, self.config.per_ip) {
+            if counts.per_ip.get(&ip).is_some_and(|count| *count >= max) {
+                return Err(Refusal::PerIp);
+            }
+        }
+
+        counts.total += 1;
+        if let Some(ip) = ip {
+            *counts.per_ip.entry(ip).or_default() += 1;
+        }
+
+        Ok(ConnectionPermit { tracker: self, ip })
+    }
+
+    /// Number of open connections.
+    pub fn count(&self) -> usize {
+        self.counts.lock().unwrap().total
+    }
+
+    fn release(&self, ip: Option<IpAddr>) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total -= 1;
+
+        if let Some(ip) = ip {
+            if let Some(count) = counts.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+impl ConnectionPermit<'_> {
+    /// Moves the connection to another address, e.g. the client address of a PROXY header,
+    /// unless that address is at `per_ip`. The connection keeps its old address if refused.
+    pub fn rekey(&mut self, ip: Option<IpAddr>) -> Result<(), Refusal> {
+        let ip = ip.map(|ip| ip.to_canonical());
+        if ip == self.ip {
+            return Ok(());
+        }
+
+        let mut counts = self.tracker.counts.lock().unwrap();
+        if let (Some(ip), Some(max)) = (ip, self.tracker.config.per_ip) {
+            if counts.per_ip.get(&ip).is_some_and(|count| *count >= max) {
+                return Err(Refusal::PerIp);
+            }
+        }
+
+        if let Some(old_ip) = self.ip {
+            if let Some(count) = counts.per_ip.get_mut(&old_ip) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.per_ip.remove(&old_ip);
+                }
+            }
+        }
+        if let Some(ip) = ip {
+            *counts.per_ip.entry(ip).or_default() += 1;
+        }
+        self.ip = ip;
+
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionPermit<'_> {
+    fn drop(&mut self) {
+        self.tracker.release(self.ip);
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{ConnectionLimitConfig, ConnectionTracker, Refusal};
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let tracker = ConnectionTracker::new(ConnectionLimitConfig {
+            total: Some(3),
+            per_ip: Some(2),
+            ..ConnectionLimitConfig::default()
+        });
+
+        let first = tracker.admit(ip("10.0.0.1")).unwrap();
+        let second = tracker.admit(ip("::ffff:10.0.0.1")).unwrap();
+        assert_eq!(tracker.admit(ip("10.0.0.1")).err(), Some(Refusal::PerIp));
+
+        let unix = tracker.admit(None).unwrap();
+        assert_eq!(tracker.admit(ip("10.0.0.2")).err(), Some(Refusal::Total));
+        assert_eq!(tracker.count(), 3);
+
+        drop(unix);
+        let other = tracker.admit(ip("10.0.0.2")).unwrap();
+        drop(first);
+        let third = tracker.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(tracker.admit(None).err(), Some(Refusal::Total));
+        drop((second, third, other));
+
+        assert_eq!(tracker.count(), 0);
+        assert!(tracker.counts.lock().unwrap().per_ip.is_empty());
+    }
+
+    #[test]
+    fn test_connection_rekey() {
+        let tracker = ConnectionTracker::new(ConnectionLimitConfig {
+            per_ip: Some(1),
+            ..ConnectionLimitConfig::default()
+        });
+
+        // connections from a proxy are keyed by the client address once it is known
+        let mut first = tracker.admit(None).unwrap();
+        let mut second = tracker.admit(None).unwrap();
+        first.rekey(ip("10.0.0.1")).unwrap();
+        assert_eq!(second.rekey(ip("10.0.0.1")), Err(Refusal::PerIp));
+        second.rekey(ip("10.0.0.2")).unwrap();
+        assert_eq!(tracker.admit(ip("10.0.0.2")).err(), Some(Refusal::PerIp));
+
+        drop(first);
+        second.rekey(ip("10.0.0.1")).unwrap();
+        drop(tracker.admit(ip("10.0.0.2")).unwrap());
+        drop(second);
+
+        assert_eq!(tracker.count(), 0);
+        assert!(tracker.counts.lock().unwrap().per_ip.is_empty());
+    }
+
+    #[test]
+    fn test_connection_unlimited() {
+        let tracker = ConnectionTracker::new(ConnectionLimitConfig::default());
+        let permits: Vec<_> = (0..100)
+            .map(|_| tracker.admit(ip("10.0.0.1")).unwrap())
+            .collect();
+        assert_eq!(tracker.count(), 100);
+
+        drop(permits);
+        assert_eq!(tracker.count(), 0);
+    }
+}
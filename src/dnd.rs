@@ -0,0 +1,284 @@
+use std::{collections::HashMap, convert::TryFrom, fmt, str::FromStr, sync::Mutex};
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Limits of the boops held back during do-not-disturb.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DndConfig {
+    /// Held boops per sender and recipient, more are refused.
+    pub max_held_per_sender: u32,
+    /// Senders with held boops per recipient.
+    pub max_held_senders: usize,
+}
+
+impl Default for DndConfig {
+    fn default() -> DndConfig {
+        DndConfig {
+            max_held_per_sender: 100,
+            max_held_senders: 100,
+        }
+    }
+}
+
+/// Do-not-disturb settings of a client, stored in the clients file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DndSettings {
+    /// Manual do-not-disturb, until it is turned off again.
+    #[serde(skip_serializing_if = "is_false")]
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHours>,
+    /// Tell senders that their boop is held back instead of delivered.
+    #[serde(skip_serializing_if = "is_false")]
+    pub notify_senders: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl DndSettings {
+    pub fn is_default(&self) -> bool {
+        *self == DndSettings::default()
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.enabled
+            || self
+                .quiet_hours
+                .as_ref()
+                .is_some_and(|quiet_hours| quiet_hours.contains(now))
+    }
+}
+
+/// Daily quiet hours in a time zone, written as `22:00-07:00 Europe/Berlin`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+    timezone: Tz,
+}
+
+impl QuietHours {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.with_timezone(&self.timezone).time();
+
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            // over midnight
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<QuietHours, String> {
+        let (range, timezone) = text
+            .split_once(' ')
+            .ok_or_else(|| format!("missing time zone: {}", text))?;
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| format!("invalid time range: {}", range))?;
+
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("invalid time: {}", time))
+        };
+        let (start, end) = (time(start)?, time(end)?);
+        if start == end {
+            return Err(format!("empty time range: {}", range));
+        }
+
+        let timezone = timezone
+            .parse()
+            .map_err(|_| format!("unknown time zone: {}", timezone))?;
+
+        Ok(QuietHours {
+            start,
+            end,
+            timezone,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<QuietHours, String> {
+        text.parse()
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(quiet_hours: QuietHours) -> String {
+        quiet_hours.to_string()
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{} {}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M"),
+            self.timezone.name()
+        )
+    }
+}
+
+/// Boops held back while their recipients are in do-not-disturb mode, counted per sender.
+/// They are only kept in memory.
+pub struct HeldBoops {
+    config: DndConfig,
+    // recipient -> (sender, count) in the order of the first held boop
+    held: Mutex<HashMap<String, Vec<(String, u32)>>>,
+}
+
+impl HeldBoops {
+    pub fn new(config: DndConfig) -> HeldBoops {
+        HeldBoops {
+            config,
+            held: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Holds a boop, unless the sender already has too many held boops for the recipient
+    /// or the recipient has held boops of too many senders.
+    pub fn hold(&self, recipient: &str, sender: &str) -> bool {
+        let mut held = self.held.lock().unwrap();
+        let senders = held.entry(String::from(recipient)).or_default();
+
+        let sender_count = senders.len();
+        match senders
+            .iter_mut()
+            .find(|(held_sender, _)| held_sender == sender)
+        {
+            Some((_, count)) if *count >= self.config.max_held_per_sender => false,
+            Some((_, count)) => {
+                *count += 1;
+                true
+            }
+            None if sender_count >= self.config.max_held_senders => false,
+            None => {
+                senders.push((String::from(sender), 1));
+                true
+            }
+        }
+    }
+
+    /// Removes and returns the held boops of the recipient.
+    pub fn take(&self, recipient: &str) -> Vec<(String, u32)> {
+        self.held
+            .lock()
+            .unwrap()
+            .remove(recipient)
+            .unwrap_or_default()
+    }
+
+    /// Recipients with held boops.
+    pub fn recipients(&self) -> Vec<String> {
+        self.held.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{DndConfig, DndSettings, HeldBoops, QuietHours};
+
+    #[test]
+    fn test_quiet_hours() {
+        let quiet_hours: QuietHours = "22:00-07:00 Europe/Berlin".parse().unwrap();
+        assert_eq!(quiet_hours.to_string(), "22:00-07:00 Europe/Berlin");
+
+        // 23:30 and 06:59 in Berlin (UTC+2 in summer)
+        assert!(quiet_hours.contains(Utc.with_ymd_and_hms(2022, 7, 1, 21, 30, 0).unwrap()));
+        assert!(quiet_hours.contains(Utc.with_ymd_and_hms(2022, 7, 1, 4, 59, 0).unwrap()));
+        // 07:00 and 12:00 in Berlin
+        assert!(!quiet_hours.contains(Utc.with_ymd_and_hms(2022, 7, 1, 5, 0, 0).unwrap()));
+        assert!(!quiet_hours.contains(Utc.with_ymd_and_hms(2022, 7, 1, 10, 0, 0).unwrap()));
+
+        let quiet_hours: QuietHours = "13:00-14:00 UTC".parse().unwrap();
+        assert!(quiet_hours.contains(Utc.with_ymd_and_hms(2022, 7, 1, 13, 0, 0).unwrap()));
+        assert!(!quiet_hours.contains(Utc.with_ymd_and_hms(2022, 7, 1, 14, 0, 0).unwrap()));
+
+        assert!("22:00-07:00".parse::<QuietHours>().is_err());
+        assert!("22:00-07:00 Mars/Olympus".parse::<QuietHours>().is_err());
+        assert!("22:00-25:00 UTC".parse::<QuietHours>().is_err());
+        assert!("22:00-22:00 UTC".parse::<QuietHours>().is_err());
+    }
+
+    #[test]
+    fn test_dnd_settings() {
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let settings: DndSettings = serde_json::from_str("{}").unwrap();
+        assert!(settings.is_default());
+        assert!(!settings.is_active(now));
+
+        let settings: DndSettings =
+            serde_json::from_str(r#"{ "quiet_hours": "11:00-13:00 UTC" }"#).unwrap();
+        assert!(settings.is_active(now));
+        assert_eq!(
+            serde_json::to_string(&settings).unwrap(),
+            r#"{"quiet_hours":"11:00-13:00 UTC"}"#
+        );
+
+        let settings = DndSettings {
+            enabled: true,
+            ..DndSettings::default()
+        };
+        assert!(settings.is_active(now));
+    }
+
+    #[test]
+    fn test_held_boops() {
+        let held = HeldBoops::new(DndConfig::default());
+        assert!(held.hold("foo", "bar"));
+        assert!(held.hold("foo", "baz"));
+        assert!(held.hold("foo", "bar"));
+        assert_eq!(held.recipients(), vec![String::from("foo")]);
+
+        assert_eq!(
+            held.take("foo"),
+            vec![(String::from("bar"), 2), (String::from("baz"), 1)]
+        );
+        assert!(held.take("foo").is_empty());
+        assert!(held.recipients().is_empty());
+    }
+
+    #[test]
+    fn test_held_boops_limits() {
+        let held = HeldBoops::new(DndConfig {
+            max_held_per_sender: 2,
+            max_held_senders: 2,
+        });
+        assert!(held.hold("foo", "bar"));
+        assert!(held.hold("foo", "bar"));
+        assert!(!held.hold("foo", "bar"));
+        assert!(held.hold("foo", "baz"));
+        assert!(!held.hold("foo", "qux"));
+        // the limits are per recipient
+        assert!(held.hold("bar", "qux"));
+
+        assert_eq!(
+            held.take("foo"),
+            vec![(String::from("bar"), 2), (String::from("baz"), 1)]
+        );
+        assert!(held.hold("foo", "qux"));
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    fmt,
+    io::Error,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+
+// (sorted pair of keys, unix timestamp in seconds, sequence number) -> entry as JSON
+const BOOPS: TableDefinition<(&str, u64, u64), &str> = TableDefinition::new("boops");
+
+/// Retention of the boop history, which is enabled with a history file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Days boops are kept.
+    pub retention_days: u64,
+    /// Boops kept per pair of keys, the oldest are dropped first.
+    pub max_per_pair: usize,
+    /// Most boops returned for a single `HISTORY` command, the newest ones.
+    pub max_results: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            retention_days: 30,
+            max_per_pair: 1000,
+            max_results: 100,
+        }
+    }
+}
+
+/// How a boop reached its recipient.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BoopKind {
+    /// `BOOP <key>`, to all connections of the recipient.
+    Boop,
+    /// `BOOP <key>/<device>`, to a single device.
+    Device,
+    /// Held during do-not-disturb and delivered afterwards.
+    Deferred,
+    /// `BOOPBACK <id>`, the answer to a relayed boop.
+    BoopBack,
+}
+
+impl BoopKind {
+    pub fn text(&self) -> &'static str {
+        match self {
+            BoopKind::Boop => "boop",
+            BoopKind::Device => "device",
+            BoopKind::Deferred => "deferred",
+            BoopKind::BoopBack => "boopback",
+        }
+    }
+}
+
+impl FromStr for BoopKind {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<BoopKind, String> {
+        match text {
+            "boop" => Ok(BoopKind::Boop),
+            "device" => Ok(BoopKind::Device),
+            "deferred" => Ok(BoopKind::Deferred),
+            "boopback" => Ok(BoopKind::BoopBack),
+            _ => Err(format!("unknown boop kind: {}", text)),
+        }
+    }
+}
+
+impl fmt::Display for BoopKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.text())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub from: String,
+    pub to: String,
+    pub timestamp: u64, // unix timestamp in seconds
+    pub kind: BoopKind,
+}
+
+/// Relayed boops, backed by an embedded database file.
+pub struct HistoryStore {
+    // `None` once closed
+    db: Mutex<Option<Arc<Database>>>,
+    config: HistoryConfig,
+    // orders boops within the same second, unique across restarts
+    seq: Arc<AtomicU64>,
+}
+
+impl HistoryStore {
+    pub async fn open(path: &Path, config: HistoryConfig) -> Result<HistoryStore, Error> {
+        let path = path.to_path_buf();
+        let db = blocking(move || {
+            let db = Database::create(path).map_err(db_error)?;
+            // create the table, reads fail on a missing table
+            let tx = db.begin_write().map_err(db_error)?;
+            tx.open_table(BOOPS).map_err(db_error)?;
+            tx.commit().map_err(db_error)?;
+
+            Ok(db)
+        })
+        .await?;
+
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_micros() as u64)
+            .unwrap_or(0);
+
+        Ok(HistoryStore {
+            db: Mutex::new(Some(Arc::new(db))),
+            config,
+            seq: Arc::new(AtomicU64::new(micros)),
+        })
+    }
+
+    /// Stores the boops and drops the oldest boops of their pairs over `max_per_pair`.
+    pub async fn record(&self, entries: Vec<HistoryEntry>) -> Result<(), Error> {
+        let db = self.db()?;
+        let seq = Arc::clone(&self.seq);
+        let max_per_pair = self.config.max_per_pair;
+
+        blocking(move || {
+            let tx = db.begin_write().map_err(db_error)?;
+            {
+                let mut table = tx.open_table(BOOPS).map_err(db_error)?;
+                for entry in &entries {
+                    let pair = pair_key(&entry.from, &entry.to);
+                    let value = serde_json::to_string(entry)?;
+                    let key = (
+                        pair.as_str(),
+                        entry.timestamp,
+                        seq.fetch_add(1, Ordering::Relaxed),
+                    );
+                    table.insert(key, value.as_str()).map_err(db_error)?;
+
+                    let mut stored = Vec::new();
+                    for item in table.range(pair_range(&pair, 0)).map_err(db_error)? {
+                        let (key, _) = item.map_err(db_error)?;
+                        let (_, timestamp, seq) = key.value();
+                        stored.push((timestamp, seq));
+                    }
+                    let excess = stored.len().saturating_sub(max_per_pair);
+                    for (timestamp, seq) in stored.into_iter().take(excess) {
+                        table
+                            .remove((pair.as_str(), timestamp, seq))
+                            .map_err(db_error)?;
+                    }
+                }
+            }
+            tx.commit().map_err(db_error)
+        })
+        .await
+    }
+
+    /// The newest boops between the two keys since the timestamp, oldest first.
+    pub async fn query(
+        &self,
+        key: &str,
+        partner: &str,
+        since: Option<u64>,
+    ) -> Result<Vec<HistoryEntry>, Error> {
+        let db = self.db()?;
+        let pair = pair_key(key, partner);
+        let since = since.unwrap_or(0);
+        let max_results = self.config.max_results;
+
+        blocking(move || {
+            let tx = db.begin_read().map_err(db_error)?;
+            let table = tx.open_table(BOOPS).map_err(db_error)?;
+
+            let mut entries = Vec::new();
+            for item in table
+                .range(pair_range(&pair, since))
+                .map_err(db_error)?
+                .rev()
+                .take(max_results)
+            {
+                let (_, value) = item.map_err(db_error)?;
+                entries.push(serde_json::from_str::<HistoryEntry>(value.value())?);
+            }
+            entries.reverse();
+
+            Ok(entries)
+        })
+        .await
+    }
+
+    /// Closes the database file, later calls fail.
+    pub fn close(&self) {
+        self.db.lock().unwrap().take();
+    }
+
+    fn db(&self) -> Result<Arc<Database>, Error> {
+        self.db
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Error::other("boop history is closed"))
+    }
+
+    /// Drops boops older than the retention period. Returns the number of dropped boops.
+    pub async fn prune(&self, now: u64) -> Result<usize, Error> {
+        let db = self.db()?;
+        let cutoff = now.saturating_sub(self.config.retention_days.saturating_mul(24 * 60 * 60));
+
+        blocking(move || {
+            let tx = db.begin_write().map_err(db_error)?;
+            let mut expired = Vec::new();
+            {
+                let mut table = tx.open_table(BOOPS).map_err(db_error)?;
+                for item in table.iter().map_err(db_error)? {
+                    let (key, _) = item.map_err(db_error)?;
+                    let (pair, timestamp, seq) = key.value();
+                    if timestamp < cutoff {
+                        expired.push((String::from(pair), timestamp, seq));
+                    }
+                }
+                for (pair, timestamp, seq) in &expired {
+                    table
+                        .remove((pair.as_str(), *timestamp, *seq))
+                        .map_err(db_error)?;
+                }
+            }
+            tx.commit().map_err(db_error)?;
+
+            Ok(expired.len())
+        })
+        .await
+    }
+}
+
+/// Both directions of a pair share their history.
+fn pair_key(key: &str, partner: &str) -> String {
+    if key <= partner {
+        format!("{} {}", key, partner)
+    } else {
+        format!("{} {}", partner, key)
+    }
+}
+
+fn pair_range(pair: &str, since: u64) -> std::ops::RangeInclusive<(&str, u64, u64)> {
+    (pair, since, 0)..=(pair, u64::MAX, u64::MAX)
+}
+
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    tokio::task::spawn_blocking(f).await.map_err(Error::other)?
+}
+
+fn db_error(err: impl Into<redb::Error>) -> Error {
+    Error::other(err.into())
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::{BoopKind, HistoryConfig, HistoryEntry, HistoryStore};
+
+    fn entry(from: &str, to: &str, timestamp: u64) -> HistoryEntry {
+        HistoryEntry {
+            from: String::from(from),
+            to: String::from(to),
+            timestamp,
+            kind: BoopKind::Boop,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history() {
+        let path = std::env::temp_dir().join(format!("boop-history-{}.redb", uuid::Uuid::new_v4()));
+        let config = HistoryConfig {
+            retention_days: 1,
+            max_per_pair: 3,
+            max_results: 2,
+        };
+        let store = HistoryStore::open(&path, config.clone()).await.unwrap();
+
+        store
+            .record(vec![
+                entry("foo", "bar", 100),
+                entry("bar", "foo", 200),
+                entry("foo", "baz", 250),
+            ])
+            .await
+            .unwrap();
+        store.record(vec![entry("foo", "bar", 300)]).await.unwrap();
+
+        // the newest boops of the pair, oldest first
+        assert_eq!(
+            store.query("bar", "foo", None).await.unwrap(),
+            vec![entry("bar", "foo", 200), entry("foo", "bar", 300)]
+        );
+        assert_eq!(
+            store.query("foo", "bar", Some(250)).await.unwrap(),
+            vec![entry("foo", "bar", 300)]
+        );
+
+        // over max_per_pair, the oldest boop is dropped
+        store.record(vec![entry("foo", "bar", 400)]).await.unwrap();
+        drop(store);
+        let store = HistoryStore::open(
+            &path,
+            HistoryConfig {
+                max_results: 10,
+                ..config
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            store.query("foo", "bar", None).await.unwrap(),
+            vec![
+                entry("bar", "foo", 200),
+                entry("foo", "bar", 300),
+                entry("foo", "bar", 400)
+            ]
+        );
+
+        // older than a day
+        assert_eq!(store.prune(24 * 60 * 60 + 300).await.unwrap(), 2);
+        assert_eq!(
+            store.query("foo", "bar", None).await.unwrap(),
+            vec![entry("foo", "bar", 300), entry("foo", "bar", 400)]
+        );
+        assert!(store.query("foo", "baz", None).await.unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
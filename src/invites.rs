@@ -0,0 +1,169 @@
+use std::{
+    collections::HashSet,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+
+use crate::unix_time;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Invite {
+    pub code: String,
+    pub expires_at: u64, // unix timestamp in seconds
+}
+
+/// Single-use invite codes, backed by the invites file.
+///
+/// The file is re-read on every operation so codes minted by the `invite` subcommand
+/// become valid without restarting the relay.
+pub struct InviteStore {
+    path: PathBuf,
+    // codes of registrations in progress, held while reading or writing the file
+    reserved: Mutex<HashSet<String>>,
+}
+
+impl InviteStore {
+    pub fn new(path: &Path) -> InviteStore {
+        InviteStore {
+            path: path.to_path_buf(),
+            reserved: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub async fn mint(&self, count: usize, ttl_secs: u64) -> Result<Vec<Invite>, Error> {
+        let _guard = self.reserved.lock().await;
+
+        let expires_at = unix_time().saturating_add(ttl_secs);
+        let new_invites: Vec<Invite> = (0..count)
+            .map(|_| Invite {
+                code: uuid::Uuid::new_v4().simple().to_string(),
+                expires_at,
+            })
+            .collect();
+
+        let mut invites = self.read_valid().await?;
+        invites.extend(new_invites.iter().cloned());
+        write_invites_file(&self.path, &invites).await?;
+
+        Ok(new_invites)
+    }
+
+    /// Reserves the invite code for a registration, until it is committed or released.
+    /// Returns `false` if it doesn't exist, has expired or is already reserved.
+    pub async fn reserve(&self, code: &str) -> Result<bool, Error> {
+        let mut reserved = self.reserved.lock().await;
+        if reserved.contains(code) {
+            return Ok(false);
+        }
+
+        let invites = self.read_valid().await?;
+        if !invites.iter().any(|invite| invite.code == code) {
+            return Ok(false);
+        }
+
+        reserved.insert(String::from(code));
+        Ok(true)
+    }
+
+    /// Consumes a reserved invite code once its account is saved.
+    pub async fn commit(&self, code: &str) -> Result<(), Error> {
+        let mut reserved = self.reserved.lock().await;
+
+        let mut invites = self.read_valid().await?;
+        invites.retain(|invite| invite.code != code);
+        write_invites_file(&self.path, &invites).await?;
+
+        reserved.remove(code);
+        Ok(())
+    }
+
+    /// Makes a reserved invite code usable again, e.g. because the key was taken.
+    pub async fn release(&self, code: &str) {
+        self.reserved.lock().await.remove(code);
+    }
+
+    async fn read_valid(&self) -> Result<Vec<Invite>, Error> {
+        let now = unix_time();
+        let mut invites = read_invites_file(&self.path).await?;
+        invites.retain(|invite| invite.expires_at > now);
+
+        Ok(invites)
+    }
+}
+
+pub async fn read_invites_file(path: &Path) -> Result<Vec<Invite>, Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(path).await?;
+    let invites: Vec<Invite> = serde_json::from_str(contents.as_str())?;
+
+    Ok(invites)
+}
+
+pub async fn write_invites_file(path: &Path, invites: &[Invite]) -> Result<(), Error> {
+    let contents = serde_json::to_string_pretty(invites)?;
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::{read_invites_file, write_invites_file, Invite, InviteStore};
+    use crate::unix_time;
+
+    fn temp_invites_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("boop-invites-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn test_invite_single_use() {
+        let path = temp_invites_path();
+        let store = InviteStore::new(&path);
+
+        let invites = store.mint(2, 60).await.unwrap();
+        assert_eq!(invites.len(), 2);
+        assert_ne!(invites[0].code, invites[1].code);
+
+        assert!(store.reserve(&invites[0].code).await.unwrap());
+        // reserved by a registration in progress
+        assert!(!store.reserve(&invites[0].code).await.unwrap());
+        store.release(&invites[0].code).await;
+        assert!(store.reserve(&invites[0].code).await.unwrap());
+        store.commit(&invites[0].code).await.unwrap();
+        assert!(!store.reserve(&invites[0].code).await.unwrap());
+        assert!(!store.reserve("doesnotexist").await.unwrap());
+
+        let remaining = read_invites_file(&path).await.unwrap();
+        assert_eq!(remaining, vec![invites[1].clone()]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_invite_expired() {
+        let path = temp_invites_path();
+        let expired = Invite {
+            code: String::from("expired"),
+            expires_at: unix_time() - 1,
+        };
+        write_invites_file(&path, &[expired]).await.unwrap();
+
+        let store = InviteStore::new(&path);
+        assert!(!store.reserve("expired").await.unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// How long connections may stay silent.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepaliveConfig {
+    /// Seconds without any command before a connection is closed, unless the client asks
+    /// for a different interval.
+    pub timeout: u64,
+    /// Bounds for the interval a client may ask for with `CONNECT <key> <pw> keepalive=<secs>`.
+    pub min: u64,
+    pub max: u64,
+    /// Send a `PING` to silent clients instead of closing the connection right away, and
+    /// close it if nothing arrives within `ping_timeout` seconds.
+    pub server_ping: bool,
+    pub ping_timeout: u64,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> KeepaliveConfig {
+        KeepaliveConfig {
+            timeout: 60,
+            min: 10,
+            max: 600,
+            server_ping: false,
+            ping_timeout: 10,
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    /// The interval for a connection: the requested one within the bounds, or the default.
+    pub fn negotiate(&self, requested: Option<u64>) -> u64 {
+        match requested {
+            Some(secs) => secs.clamp(self.min, self.max.max(self.min)),
+            None => self.timeout,
+        }
+    }
+
+    pub fn ping_timeout(&self) -> Option<Duration> {
+        self.server_ping
+            .then(|| Duration::from_secs(self.ping_timeout))
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::KeepaliveConfig;
+
+    #[test]
+    fn test_keepalive_negotiate() {
+        let config = KeepaliveConfig::default();
+        assert_eq!(config.negotiate(None), 60);
+        assert_eq!(config.negotiate(Some(120)), 120);
+        assert_eq!(config.negotiate(Some(1)), 10);
+        assert_eq!(config.negotiate(Some(86400)), 600);
+
+        let config = KeepaliveConfig {
+            min: 30,
+            max: 20,
+            ..KeepaliveConfig::default()
+        };
+        assert_eq!(config.negotiate(Some(10)), 30);
+        assert_eq!(config.negotiate(Some(100)), 30);
+        assert_eq!(config.ping_timeout(), None);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::BTreeMap,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use tokio::{fs, sync::Mutex};
+
+/// When keys were last online, backed by the last-seen file.
+pub struct LastSeenStore {
+    path: PathBuf,
+    // held while writing so the file is written in order
+    seen: Mutex<Seen>,
+}
+
+struct Seen {
+    times: BTreeMap<String, u64>,
+    // no more writes after the relay shut down
+    closed: bool,
+}
+
+impl LastSeenStore {
+    /// Reads the last-seen file, a missing file is an empty store.
+    pub async fn open(path: &Path) -> Result<LastSeenStore, Error> {
+        let times = read_last_seen_file(path).await?;
+
+        Ok(LastSeenStore {
+            path: path.to_path_buf(),
+            seen: Mutex::new(Seen {
+                times,
+                closed: false,
+            }),
+        })
+    }
+
+    /// Unix timestamp in seconds of the key's last disconnect.
+    pub async fn get(&self, key: &str) -> Option<u64> {
+        self.seen.lock().await.times.get(key).copied()
+    }
+
+    /// Records the keys as last seen at `now` and writes the updated file. Does nothing
+    /// once the store is closed.
+    pub async fn record(&self, keys: &[String], now: u64) -> Result<(), Error> {
+        self.update(keys, now, false).await
+    }
+
+    /// Records the keys that are still online at shutdown, later calls to `record` are
+    /// ignored.
+    pub async fn close(&self, keys: &[String], now: u64) -> Result<(), Error> {
+        self.update(keys, now, true).await
+    }
+
+    async fn update(&self, keys: &[String], now: u64, close: bool) -> Result<(), Error> {
+        let mut seen = self.seen.lock().await;
+        if seen.closed {
+            return Ok(());
+        }
+        seen.closed = close;
+
+        for key in keys {
+            seen.times.insert(key.clone(), now);
+        }
+        write_last_seen_file(&self.path, &seen.times).await
+    }
+}
+
+pub async fn read_last_seen_file(path: &Path) -> Result<BTreeMap<String, u64>, Error> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let contents = fs::read_to_string(path).await?;
+    let seen: BTreeMap<String, u64> = serde_json::from_str(contents.as_str())?;
+
+    Ok(seen)
+}
+
+pub async fn write_last_seen_file(path: &Path, seen: &BTreeMap<String, u64>) -> Result<(), Error> {
+    let contents = serde_json::to_string_pretty(seen)?;
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::LastSeenStore;
+
+    #[tokio::test]
+    async fn test_last_seen_persisted() {
+        let path =
+            std::env::temp_dir().join(format!("boop-last-seen-{}.json", uuid::Uuid::new_v4()));
+
+        let store = LastSeenStore::open(&path).await.unwrap();
+        assert_eq!(store.get("foo").await, None);
+
+        store
+            .record(&[String::from("foo"), String::from("bar")], 100)
+            .await
+            .unwrap();
+        store.record(&[String::from("foo")], 200).await.unwrap();
+
+        let store = LastSeenStore::open(&path).await.unwrap();
+        assert_eq!(store.get("foo").await, Some(200));
+        assert_eq!(store.get("bar").await, Some(100));
+
+        store.close(&[], 300).await.unwrap();
+        store.record(&[String::from("foo")], 400).await.unwrap();
+        let store = LastSeenStore::open(&path).await.unwrap();
+        assert_eq!(store.get("foo").await, Some(200));
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
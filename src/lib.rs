@@ -0,0 +1,53 @@
+//! Relay server for cute snoot boops.
+//!
+//! Besides the server itself ([`ServerBuilder`]), the crate exposes the line protocol
+//! ([`message`]), the client store ([`clients`]) and an async client ([`client`]) for
+//! tools talking to a relay.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[macro_use]
+extern crate log;
+
+#[cfg(unix)]
+mod admin;
+pub mod client;
+pub mod clients;
+pub mod config;
+pub mod connlimit;
+pub mod dnd;
+pub mod history;
+pub mod invites;
+pub mod keepalive;
+pub mod lastseen;
+mod listener;
+pub mod message;
+mod motd;
+pub mod outbox;
+pub mod pairstats;
+pub mod proxy;
+pub mod ratelimit;
+mod registry;
+pub mod replies;
+pub mod schedule;
+mod server;
+pub mod settings;
+pub mod stats;
+pub mod webhooks;
+
+pub use listener::ListenAddr;
+pub use server::{ServerBuilder, ServerHandle};
+
+/// Shorthand for the transmit half of the message channel.
+type Tx = outbox::Sender;
+
+/// Shorthand for the receive half of the message channel.
+type Rx = outbox::Receiver;
+
+/// Current time as a unix timestamp in seconds.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,167 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+
+#[cfg(unix)]
+use std::path::Path;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio_rustls::TlsAcceptor;
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Where a running server accepts connections.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tls(SocketAddr),
+    /// Plaintext TCP, e.g. behind a TLS-terminating proxy.
+    Plain(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tls(addr) => write!(f, "{} (tls)", addr),
+            ListenAddr::Plain(addr) => write!(f, "{} (plaintext)", addr),
+            ListenAddr::Unix(path) => write!(f, "{} (unix)", path.display()),
+        }
+    }
+}
+
+impl Accepted {
+    pub(crate) fn peer(&self) -> Peer {
+        match self {
+            Accepted::Tls(_, peer_addr, _) | Accepted::Plain(_, peer_addr) => Peer::Tcp(*peer_addr),
+            #[cfg(unix)]
+            Accepted::Unix(_) => Peer::Unix,
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp {
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+    },
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Where a connection comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Peer {
+    Tcp(SocketAddr),
+    Unix,
+}
+
+impl Peer {
+    pub(crate) fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Tcp(addr) => Some(addr.ip()),
+            Peer::Unix => None,
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::Unix => write!(f, "unix socket"),
+        }
+    }
+}
+
+pub(crate) enum Accepted {
+    Tls(TcpStream, SocketAddr, TlsAcceptor),
+    Plain(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    pub(crate) fn local_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            Listener::Tcp {
+                listener,
+                tls: Some(_),
+            } => Ok(ListenAddr::Tls(listener.local_addr()?)),
+            Listener::Tcp {
+                listener,
+                tls: None,
+            } => Ok(ListenAddr::Plain(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let addr = listener.local_addr()?;
+                let path = addr
+                    .as_pathname()
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+                Ok(ListenAddr::Unix(path.to_path_buf()))
+            }
+        }
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<Accepted> {
+        match self {
+            Listener::Tcp {
+                listener,
+                tls: Some(acceptor),
+            } => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok(Accepted::Tls(stream, peer_addr, acceptor.clone()))
+            }
+            Listener::Tcp {
+                listener,
+                tls: None,
+            } => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok(Accepted::Plain(stream, peer_addr))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _peer_addr) = listener.accept().await?;
+                Ok(Accepted::Unix(stream))
+            }
+        }
+    }
+}
+
+/// Binds a TCP listener. IPv6 listeners only accept IPv6 connections, so the same port can
+/// be bound for IPv4 and IPv6 separately.
+pub(crate) fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds a unix socket listener, replacing a stale socket file.
+#[cfg(unix)]
+pub(crate) fn bind_unix(path: &Path) -> io::Result<Listener> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(Listener::Unix(UnixListener::bind(path)?))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn bind_unix(_path: &std::path::Path) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets are only available on unix systems",
+    ))
+}
@@ -1,58 +1,15 @@
 use argh::FromArgs;
-use rustls_pemfile::{certs, pkcs8_private_keys};
 use std::{
-    collections::HashMap,
-    fs::File,
     io::{self, Error},
-    net::ToSocketAddrs,
     path::{Path, PathBuf},
-    sync::Arc,
-    time::Duration,
-};
-use tokio::{
-    io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader, WriteHalf},
-    net::TcpListener,
-    net::TcpStream,
-    sync::mpsc::unbounded_channel,
-    sync::{mpsc, Mutex},
-};
-use tokio_rustls::{
-    rustls::{self, Certificate, PrivateKey},
-    {server::TlsStream, TlsAcceptor},
 };
 
+use boop_relay::{config, invites::InviteStore, ServerBuilder};
 use flexi_logger::{Duplicate, FileSpec, Logger, WriteMode};
 #[macro_use]
 extern crate log;
 
-mod clients;
-mod message;
-use clients::{client_login_is_valid, Client};
-use message::{create_message_text, parse_message, MessageErrorKind, MessageType};
-
-/// Shorthand for the transmit half of the message channel.
-type Tx = mpsc::UnboundedSender<MessageType>;
-
-/// Shorthand for the receive half of the message channel.
-type Rx = mpsc::UnboundedReceiver<MessageType>;
-
-type SecuredSharedState = Arc<Mutex<SharedState>>;
-
-struct SharedState {
-    // User-Key -> Connection-ID -> Channel
-    connections: HashMap<String, HashMap<String, Tx>>,
-}
-
-impl SharedState {
-    fn new() -> SharedState {
-        SharedState {
-            connections: HashMap::new(),
-        }
-    }
-}
-
 const LOG_DIR: &str = "logs";
-const AFK_TIMEOUT_SECS: u64 = 30;
 
 #[derive(FromArgs, Debug)]
 /// TLS-Server providing the backend for cute snoot boops
@@ -61,9 +18,24 @@ struct BoopOptions {
     #[argh(positional)]
     clients_config: PathBuf,
 
-    /// bind ip address with port
+    /// bind addresses with port for TLS connections, host names listen on all of their
+    /// addresses
     #[argh(positional)]
-    addr: String,
+    addrs: Vec<String>,
+
+    /// bind address with port for plaintext connections (loopback only, unless
+    /// --plain-public is set), can be repeated
+    #[argh(option)]
+    plain: Vec<String>,
+
+    /// allow plaintext connections on non-loopback addresses, e.g. behind a
+    /// TLS-terminating proxy
+    #[argh(switch)]
+    plain_public: bool,
+
+    /// unix socket for plaintext connections, can be repeated
+    #[argh(option)]
+    unix: Vec<PathBuf>,
 
     /// show debug logging
     #[argh(switch, short = 'd')]
@@ -71,29 +43,78 @@ struct BoopOptions {
 
     /// tls cert file
     #[argh(option, short = 'c')]
-    cert: PathBuf,
+    cert: Option<PathBuf>,
 
     /// tls key file
     #[argh(option, short = 'k')]
-    key: PathBuf,
+    key: Option<PathBuf>,
+
+    /// invite codes file (default: invites.json next to the client config file)
+    #[argh(option, short = 'i')]
+    invites: Option<PathBuf>,
+
+    /// relay config file (json), see README
+    #[argh(option)]
+    config: Option<PathBuf>,
+
+    /// message of the day file, sent to clients after login
+    #[argh(option, short = 'm')]
+    motd: Option<PathBuf>,
+
+    /// unix socket for the admin interface (disabled if not set)
+    #[argh(option, short = 'a')]
+    admin_socket: Option<PathBuf>,
+
+    /// boop history database, enables the HISTORY command (disabled if not set)
+    #[argh(option)]
+    history: Option<PathBuf>,
+
+    #[argh(subcommand)]
+    command: Option<BoopCommand>,
 }
 
-fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
-    certs(&mut std::io::BufReader::new(File::open(path)?))
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))
-        .map(|mut certs| certs.drain(..).map(Certificate).collect())
+impl BoopOptions {
+    fn invites_path(&self) -> PathBuf {
+        self.invites
+            .clone()
+            .unwrap_or_else(|| self.clients_config.with_file_name("invites.json"))
+    }
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum BoopCommand {
+    Invite(InviteOptions),
+}
+
+#[derive(FromArgs, Debug)]
+/// Mint single-use invite codes for self-registration
+#[argh(subcommand, name = "invite")]
+struct InviteOptions {
+    /// number of invite codes to create
+    #[argh(option, short = 'n', default = "1")]
+    count: usize,
+
+    /// hours until the invite codes expire
+    #[argh(option, short = 'e', default = "72")]
+    expires: u64,
 }
 
-fn load_keys(path: &Path) -> io::Result<Vec<PrivateKey>> {
-    pkcs8_private_keys(&mut std::io::BufReader::new(File::open(path)?))
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid key"))
-        .map(|mut keys| keys.drain(..).map(PrivateKey).collect())
+fn missing_option(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("missing required option: {}", name),
+    )
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let options: BoopOptions = argh::from_env();
 
+    if let Some(BoopCommand::Invite(invite_options)) = &options.command {
+        return mint_invites(&options.invites_path(), invite_options).await;
+    }
+
     tokio::fs::create_dir_all(LOG_DIR)
         .await
         .expect("failed to create logging directory");
@@ -119,242 +140,73 @@ async fn main() -> Result<(), Error> {
 
     debug!("debug logging active");
 
-    let clients = clients::read_clients_file(&options.clients_config)
-        .await
-        .expect("couldn't read clients config");
-    info!("{} client entries read", clients.len());
-
-    let addr = options
-        .addr
-        .to_socket_addrs()?
-        .next()
-        .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
-    let certs = load_certs(&options.cert)?;
-    let mut keys = load_keys(&options.key)?;
-    info!("{} TLS certs, {} TLS keys read", certs.len(), keys.len());
-
-    let config = rustls::ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(certs, keys.remove(0))
-        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
-
-    let acceptor = TlsAcceptor::from(Arc::new(config));
-    let listener = TcpListener::bind(&addr).await?;
-
-    info!("started server on {}", options.addr);
-
-    let state = Arc::new(Mutex::new(SharedState::new()));
-
-    loop {
-        let (stream, _peer_addr) = listener.accept().await?;
-        let acceptor = acceptor.clone();
-        let clients_list = clients.clone();
-
-        let state = Arc::clone(&state);
-
-        tokio::spawn(async move {
-            debug!("received connection attempt, trying tls handshake");
-
-            if let Err(err) = handle_connection(&acceptor, stream, &clients_list, state).await {
-                if err.kind() == io::ErrorKind::ConnectionReset {
-                    warn!("client forcefully closed the connection");
-                } else {
-                    error!("connection error [{}]: {}", err.kind(), err);
-                }
-            }
-        });
-    }
-}
+    let config = match &options.config {
+        Some(path) => config::read_config_file(path)
+            .await
+            .expect("couldn't read relay config"),
+        None => Default::default(),
+    };
 
-async fn handle_connection(
-    acceptor: &TlsAcceptor,
-    stream: TcpStream,
-    clients: &Vec<Client>,
-    state: Arc<Mutex<SharedState>>,
-) -> io::Result<()> {
-    let stream = acceptor.accept(stream).await?;
-    let (readhalf, mut writehalf) = split(stream);
-    let mut reader = BufReader::new(readhalf);
-
-    // check for connect call
-    let mut cmd_buffer = String::new();
-    let read_result = reader.read_line(&mut cmd_buffer).await;
-    if let Err(err) = read_result {
-        error!("there was an error reading from the connection: {}", &err);
-        return Err(err);
+    if options.addrs.is_empty() && options.plain.is_empty() && options.unix.is_empty() {
+        return Err(missing_option("addr"));
     }
 
-    // Initial Handshake
-
-    let read = read_result.unwrap();
-    if read == 0 {
-        error!("EOF reached while reading from connection");
-        return Err(Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "EOF reached while reading from connection",
-        ));
+    let mut builder = ServerBuilder::new(&options.clients_config)
+        .allow_public_plain(options.plain_public)
+        .invites(options.invites_path())
+        .config(config);
+    if !options.addrs.is_empty() {
+        let cert = options
+            .cert
+            .clone()
+            .ok_or_else(|| missing_option("--cert"))?;
+        let key = options.key.clone().ok_or_else(|| missing_option("--key"))?;
+        builder = builder.tls_files(cert, key);
     }
-
-    let parser_res = parse_message(&cmd_buffer);
-    if let Err(err) = parser_res {
-        return send_error_and_close(writehalf, err.into()).await;
+    for addr in &options.addrs {
+        builder = builder.listen(addr);
     }
-
-    let client_key;
-    if let MessageType::CONNECT(key, password) = parser_res.unwrap() {
-        // CORRECT CONNECT CALL
-
-        let login_result = client_login_is_valid(&key, &password, clients);
-        if login_result.is_err() || !login_result.unwrap() {
-            // LOGIN WRONG
-            info!("login failed, key: {}", &key);
-            return send_message_and_close(writehalf, MessageType::NO).await;
-        } else {
-            // LOGIN CORRECT
-            info!("logged in: {}", &key);
-            send_message(&mut writehalf, MessageType::HEY).await?;
-            client_key = key;
-        }
-    } else {
-        // COMMAND SYNTAX IS CORRECT BUT ITS NOT A CONNECT CALL -> REFUSE
-        return send_error_and_close(writehalf, MessageErrorKind::ProtocolMismatch).await;
+    for addr in &options.plain {
+        builder = builder.listen_plain(addr);
     }
-
-    // add client connection
-    let connection_id = uuid::Uuid::new_v4().to_string();
-    let (tx, mut rx): (Tx, Rx) = unbounded_channel();
-    let mut watchdog = tokio::time::interval(Duration::from_secs(AFK_TIMEOUT_SECS));
-    watchdog.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay); // if tick is missed, fire next tick asap and then wait the full afk timeout again
-    let mut was_pinged = true;
-
-    // add connection to state
-    add_connection(&client_key, &connection_id, tx, &state).await;
-
-    loop {
-        let mut buf = String::new();
-        tokio::select! {
-            _ = watchdog.tick() => {
-                if !was_pinged {
-                    debug!("connection {} timed out", {&connection_id});
-                    writehalf.shutdown().await?;
-                    remove_connection(&client_key, connection_id, &state).await;
-                    return Ok(());
-                }
-                else {
-                    was_pinged = false;
-                }
-            },
-            res = reader.read_line(&mut buf) => match res {
-                Ok(n) => {
-                    if n == 0 { //EOF while reading
-                        remove_connection(&client_key, connection_id, &state).await;
-                        return Err(Error::from(io::ErrorKind::UnexpectedEof));
-                    }
-
-                    debug!("{}", &buf);
-                    let parse_result = parse_message(&buf);
-                    if let Ok(msg) = parse_result {
-                        match msg {
-                            MessageType::DISCONNECT => {
-                                remove_connection(&client_key, connection_id, &state).await;
-                                return send_message_and_close(writehalf, MessageType::BYE).await;
-                            },
-                            MessageType::PING => {
-                                send_message(&mut writehalf, MessageType::PONG).await?;
-                                was_pinged = true;
-                            },
-                            MessageType::BOOP(partner_key) => {
-                                let state = state.lock().await;
-
-                                if let Some(inner_map) = state.connections.get(&partner_key) {
-                                    for (_, channel) in inner_map {
-                                        let _ = channel.send(MessageType::BOOP(client_key.clone()));
-                                    }
-                                }
-                            },
-                            MessageType::AYT(partner_key) => {
-                                let state = state.lock().await;
-
-                                let msg = if state.connections.contains_key(&partner_key) {
-                                    MessageType::ONLINE(partner_key)
-                                }
-                                else {
-                                    MessageType::AFK(partner_key)
-                                };
-
-                                send_message(&mut writehalf, msg).await?;
-                            },
-                            _ => {
-                                // against protocol -> disconnect
-                                return send_error_and_close(writehalf, message::MessageErrorKind::ProtocolMismatch).await;
-                            }
-                        }
-                    }
-                    else { //close connection on non-compliant message
-                        return send_error_and_close(writehalf, parse_result.unwrap_err().into()).await;
-                    }
-                },
-                Err(err) => { //close connection on read error
-                    error!("there was an error reading from the connection ({})... closing", &err);
-                    remove_connection(&client_key, connection_id, &state).await;
-                    return writehalf.shutdown().await;
-                },
-            },
-            Some(msg) = rx.recv() => {
-                send_message(&mut writehalf, msg).await?;
-            }
-        }
+    for path in &options.unix {
+        builder = builder.listen_unix(path);
+    }
+    if let Some(motd) = &options.motd {
+        builder = builder.motd(motd);
+    }
+    if let Some(admin_socket) = &options.admin_socket {
+        builder = builder.admin_socket(admin_socket);
+    }
+    if let Some(history) = &options.history {
+        builder = builder.history(history);
     }
-}
-
-async fn add_connection(
-    client_key: &String,
-    connection_id: &String,
-    channel: Tx,
-    state: &SecuredSharedState,
-) {
-    let mut state = state.lock().await;
-
-    state
-        .connections
-        .entry(client_key.clone())
-        .or_insert(HashMap::new())
-        .insert(connection_id.clone(), channel);
-}
-
-async fn remove_connection(client_key: &String, connection_id: String, state: &SecuredSharedState) {
-    let mut state = state.lock().await;
-
-    state
-        .connections
-        .entry(client_key.clone())
-        .and_modify(|inner_map| {
-            inner_map.remove(&connection_id);
-        });
 
-    state.connections.retain(|_, inner_map| inner_map.len() > 0);
+    let server = builder.start().await?;
+    server
+        .run_until(async {
+            if let Err(err) = tokio::signal::ctrl_c().await {
+                warn!("can't listen for ctrl-c: {}", err);
+                std::future::pending::<()>().await;
+            }
+            info!("received ctrl-c, shutting down");
+        })
+        .await
 }
 
-async fn send_error_and_close(
-    writehalf: WriteHalf<TlsStream<TcpStream>>,
-    err: message::MessageErrorKind,
-) -> io::Result<()> {
-    send_message_and_close(writehalf, MessageType::ERROR(err)).await
-}
+async fn mint_invites(path: &Path, options: &InviteOptions) -> Result<(), Error> {
+    let store = InviteStore::new(path);
+    let ttl_secs = options.expires.checked_mul(60 * 60).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invite expiry of {} hours is too long", options.expires),
+        )
+    })?;
+    let invites = store.mint(options.count, ttl_secs).await?;
 
-async fn send_message_and_close(
-    mut writehalf: WriteHalf<TlsStream<TcpStream>>,
-    message: message::MessageType,
-) -> io::Result<()> {
-    send_message(&mut writehalf, message).await?;
-    writehalf.shutdown().await
-}
+    for invite in invites {
+        println!("{}", invite.code);
+    }
 
-async fn send_message(
-    writehalf: &mut WriteHalf<TlsStream<TcpStream>>,
-    message: message::MessageType,
-) -> io::Result<()> {
-    let msg_text = create_message_text(message);
-    writehalf.write_all(msg_text.as_bytes()).await
+    Ok(())
 }
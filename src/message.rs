@@ -1,28 +1,215 @@
-#[derive(Debug, PartialEq)]
+use crate::{
+    clients::key_is_valid,
+    dnd::QuietHours,
+    history::{BoopKind, HistoryEntry},
+    pairstats::PairCounts,
+};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum MessageType {
     // usually requests
-    CONNECT(String, String), //key, password
+    CONNECT(String, String, LoginOptions), //key, password, options
+    REGISTER(String, String, String),      //invite, key, password
     DISCONNECT,
     PING,
-    BOOP(String), //partner_key
-    AYT(String),  //partner_key
+    BOOP(String, Option<u64>),  //partner_key, boop id (only when relayed)
+    SCHEDULE(String, BoopTime), //partner_key, delivery time
+    BOOPBACK(Option<String>, u64), //partner_key (only when relayed), boop id
+    AYT(String),                //partner_key
+    DEVICES(String),            //partner_key
+    STATUS(PresenceState, Option<String>), //state, text
+    DND(DndCommand),
+    LASTSEEN(bool),               //share
+    BLOCK(String),                //partner_key
+    UNBLOCK(String),              //partner_key
+    BLOCKED(Vec<String>),         //blocked keys, empty when asking for the list
+    HISTORY(String, Option<u64>), //partner_key, since (unix timestamp)
+    STATS(String),                //partner_key
+    CANCEL(u64),                  //id of a scheduled boop
 
     // usually responses
     HEY,
     NO,
+    BANNED(Option<String>), //reason
     BYE,
     PONG,
     ERROR(MessageErrorKind),
-    ONLINE(String),
-    AFK(String),
+    BOOPS(String, u32),                              //partner_key, count
+    ONLINE(String, Vec<String>),                     //partner_key, device names (only for DEVICES)
+    AFK(String, Option<u64>),                        //partner_key, last seen (unix timestamp)
+    NOTICE(String),                                  //text
+    KEEPALIVE(u64),                                  //seconds
+    PRESENCE(String, PresenceState, Option<String>), //partner_key, state, text
+    DEFERRED(String),                                //partner_key
+    ENTRIES(String, u32),                            //partner_key, number of following ENTRY lines
+    ENTRY(HistoryEntry),
+    COUNTS(PairCounts),
+    PENDING(Option<u32>), //number of following SCHEDULED lines, none when asking for the list
+    SCHEDULED(u64, String, u64), //id, partner_key, delivery time (unix timestamp)
 }
 
-#[derive(Debug, PartialEq)]
+/// Longest status text in characters.
+pub const MAX_STATUS_LENGTH: usize = 100;
+
+/// Presence state set with `STATUS`, ordered from most to least available.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum PresenceState {
+    #[default]
+    Available,
+    Busy,
+    Away,
+    DoNotDisturb,
+}
+
+impl PresenceState {
+    fn parse(text: &str) -> Result<PresenceState, ParserError> {
+        match text.to_ascii_lowercase().as_str() {
+            "available" => Ok(PresenceState::Available),
+            "busy" => Ok(PresenceState::Busy),
+            "away" => Ok(PresenceState::Away),
+            "dnd" => Ok(PresenceState::DoNotDisturb),
+            _ => Err(ParserError::UnknownArguments),
+        }
+    }
+
+    pub fn text(&self) -> &'static str {
+        match self {
+            PresenceState::Available => "available",
+            PresenceState::Busy => "busy",
+            PresenceState::Away => "away",
+            PresenceState::DoNotDisturb => "dnd",
+        }
+    }
+}
+
+/// Changes to the do-not-disturb settings with `DND`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DndCommand {
+    /// `DND ON` / `DND OFF`
+    Enabled(bool),
+    /// `DND QUIET 22:00-07:00 Europe/Berlin` / `DND QUIET OFF`
+    QuietHours(Option<QuietHours>),
+    /// `DND NOTIFY ON` / `DND NOTIFY OFF`
+    NotifySenders(bool),
+}
+
+impl DndCommand {
+    fn text(&self) -> String {
+        match self {
+            DndCommand::Enabled(enabled) => String::from(on_off_text(*enabled)),
+            DndCommand::QuietHours(None) => String::from("QUIET OFF"),
+            DndCommand::QuietHours(Some(quiet_hours)) => format!("QUIET {}", quiet_hours),
+            DndCommand::NotifySenders(notify) => format!("NOTIFY {}", on_off_text(*notify)),
+        }
+    }
+}
+
+/// Delivery time of a boop scheduled with `BOOP <key> AT <time>` or `BOOP <key> IN <seconds>`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BoopTime {
+    /// Unix timestamp in seconds.
+    At(u64),
+    /// Seconds from now.
+    In(u64),
+}
+
+impl BoopTime {
+    /// The unix timestamp of the delivery.
+    pub fn resolve(&self, now: u64) -> u64 {
+        match self {
+            BoopTime::At(time) => *time,
+            BoopTime::In(secs) => now.saturating_add(*secs),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            BoopTime::At(time) => format!("AT {}", time),
+            BoopTime::In(secs) => format!("IN {}", secs),
+        }
+    }
+}
+
+fn on_off(text: &str) -> Result<bool, ParserError> {
+    match text.to_ascii_uppercase().as_str() {
+        "ON" => Ok(true),
+        "OFF" => Ok(false),
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn on_off_text(value: bool) -> &'static str {
+    if value {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+/// Optional `name=value` settings after the credentials of a `CONNECT` call, e.g.
+/// `CONNECT foo bar keepalive=120`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LoginOptions {
+    /// Seconds the client may stay silent before the relay closes the connection.
+    pub keepalive: Option<u64>,
+    /// Name of the device, shown to partners and used to boop this device only.
+    pub device: Option<String>,
+    /// Follow `ONLINE` answers with the partner's `PRESENCE`.
+    pub presence: bool,
+    /// Relay boops with the IDs used by `BOOPBACK`.
+    pub ids: bool,
+}
+
+impl LoginOptions {
+    fn parse(args: &[&str]) -> Result<LoginOptions, ParserError> {
+        let mut options = LoginOptions::default();
+        for arg in args {
+            match arg.split_once('=') {
+                Some(("keepalive", secs)) if options.keepalive.is_none() => {
+                    options.keepalive =
+                        Some(secs.parse().map_err(|_| ParserError::UnknownArguments)?);
+                }
+                Some(("device", name)) if options.device.is_none() && key_is_valid(name) => {
+                    options.device = Some(String::from(name));
+                }
+                Some(("presence", "on")) => options.presence = true,
+                Some(("ids", "on")) => options.ids = true,
+                _ => return Err(ParserError::UnknownArguments),
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::new();
+        if let Some(secs) = self.keepalive {
+            text.push_str(&format!(" keepalive={}", secs));
+        }
+        if let Some(name) = &self.device {
+            text.push_str(&format!(" device={}", name));
+        }
+        if self.presence {
+            text.push_str(" presence=on");
+        }
+        if self.ids {
+            text.push_str(" ids=on");
+        }
+
+        text
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum MessageErrorKind {
     NotAvailable,
     MalformedCommand,
     MalformedArguments,
     ProtocolMismatch,
+    RateLimited,
+    TooManyConnections,
+    TooManySessions,
 }
 
 #[derive(Debug, PartialEq)]
@@ -31,35 +218,86 @@ pub enum ParserError {
     UnknownArguments,
 }
 
-impl Into<MessageErrorKind> for ParserError {
-    fn into(self) -> MessageErrorKind {
-        match self {
+impl From<ParserError> for MessageErrorKind {
+    fn from(err: ParserError) -> MessageErrorKind {
+        match err {
             ParserError::UnknownMessageType => MessageErrorKind::MalformedCommand,
             ParserError::UnknownArguments => MessageErrorKind::MalformedArguments,
         }
     }
 }
 
-fn connect(args: &Vec<&str>) -> Result<MessageType, ParserError> {
-    if args.len() == 2 {
+fn connect(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() >= 2 {
         Ok(MessageType::CONNECT(
             String::from(args[0]),
             String::from(args[1]),
+            LoginOptions::parse(&args[2..])?,
         ))
     } else {
         Err(ParserError::UnknownArguments)
     }
 }
 
-fn boop(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn register(args: &[&str]) -> Result<MessageType, ParserError> {
+    // empty arguments come from repeated spaces, e.g. an empty password
+    if args.len() == 3 && args.iter().all(|arg| !arg.is_empty()) {
+        Ok(MessageType::REGISTER(
+            String::from(args[0]),
+            String::from(args[1]),
+            String::from(args[2]),
+        ))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn boop(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [partner_key] => Ok(MessageType::BOOP(String::from(*partner_key), None)),
+        [partner_key, id] => match id.parse::<u64>() {
+            Ok(id) => Ok(MessageType::BOOP(String::from(*partner_key), Some(id))),
+            Err(_) => Err(ParserError::UnknownArguments),
+        },
+        [partner_key, kind, time] => {
+            let time = time
+                .parse::<u64>()
+                .map_err(|_| ParserError::UnknownArguments)?;
+            let time = match kind.to_ascii_uppercase().as_str() {
+                "AT" => BoopTime::At(time),
+                "IN" => BoopTime::In(time),
+                _ => return Err(ParserError::UnknownArguments),
+            };
+
+            Ok(MessageType::SCHEDULE(String::from(*partner_key), time))
+        }
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn boops(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() == 2 {
+        match args[1].parse::<u32>() {
+            Ok(count) if count > 0 => Ok(MessageType::BOOPS(String::from(args[0]), count)),
+            _ => Err(ParserError::UnknownArguments),
+        }
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn keepalive(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
-        Ok(MessageType::BOOP(String::from(args[0])))
+        match args[0].parse::<u64>() {
+            Ok(secs) => Ok(MessageType::KEEPALIVE(secs)),
+            Err(_) => Err(ParserError::UnknownArguments),
+        }
     } else {
         Err(ParserError::UnknownArguments)
     }
 }
 
-fn ayt(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn ayt(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
         Ok(MessageType::AYT(String::from(args[0])))
     } else {
@@ -67,29 +305,283 @@ fn ayt(args: &Vec<&str>) -> Result<MessageType, ParserError> {
     }
 }
 
-fn online(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn devices(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
-        Ok(MessageType::ONLINE(String::from(args[0])))
+        Ok(MessageType::DEVICES(String::from(args[0])))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn status_text(args: &[&str]) -> Result<Option<String>, ParserError> {
+    let text = args.join(" ");
+    if text.chars().count() > MAX_STATUS_LENGTH {
+        Err(ParserError::UnknownArguments)
+    } else if text.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(text))
+    }
+}
+
+fn status(args: &[&str]) -> Result<MessageType, ParserError> {
+    Ok(MessageType::STATUS(
+        PresenceState::parse(args[0])?,
+        status_text(&args[1..])?,
+    ))
+}
+
+fn dnd(args: &[&str]) -> Result<MessageType, ParserError> {
+    let command = match (args[0].to_ascii_uppercase().as_str(), &args[1..]) {
+        (_, []) => DndCommand::Enabled(on_off(args[0])?),
+        ("QUIET", [off]) if off.eq_ignore_ascii_case("OFF") => DndCommand::QuietHours(None),
+        ("QUIET", quiet_hours) => DndCommand::QuietHours(Some(
+            quiet_hours
+                .join(" ")
+                .parse()
+                .map_err(|_| ParserError::UnknownArguments)?,
+        )),
+        ("NOTIFY", [notify]) => DndCommand::NotifySenders(on_off(notify)?),
+        _ => return Err(ParserError::UnknownArguments),
+    };
+
+    Ok(MessageType::DND(command))
+}
+
+fn deferred(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() == 1 {
+        Ok(MessageType::DEFERRED(String::from(args[0])))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn presence(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() >= 2 {
+        Ok(MessageType::PRESENCE(
+            String::from(args[0]),
+            PresenceState::parse(args[1])?,
+            status_text(&args[2..])?,
+        ))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn online(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.iter().all(|arg| !arg.is_empty()) {
+        Ok(MessageType::ONLINE(
+            String::from(args[0]),
+            args[1..]
+                .iter()
+                .map(|device| String::from(*device))
+                .collect(),
+        ))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn afk(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [partner_key] => Ok(MessageType::AFK(String::from(*partner_key), None)),
+        [partner_key, last_seen] => match last_seen.parse::<u64>() {
+            Ok(last_seen) => Ok(MessageType::AFK(
+                String::from(*partner_key),
+                Some(last_seen),
+            )),
+            Err(_) => Err(ParserError::UnknownArguments),
+        },
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn block(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [partner_key] if key_is_valid(partner_key) => {
+            Ok(MessageType::BLOCK(String::from(*partner_key)))
+        }
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn unblock(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [partner_key] if key_is_valid(partner_key) => {
+            Ok(MessageType::UNBLOCK(String::from(*partner_key)))
+        }
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn blocked(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.iter().all(|key| key_is_valid(key)) {
+        Ok(MessageType::BLOCKED(
+            args.iter().map(|key| String::from(*key)).collect(),
+        ))
     } else {
         Err(ParserError::UnknownArguments)
     }
 }
 
-fn afk(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn history(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [partner_key] => Ok(MessageType::HISTORY(String::from(*partner_key), None)),
+        [partner_key, since] => match since.parse::<u64>() {
+            Ok(since) => Ok(MessageType::HISTORY(
+                String::from(*partner_key),
+                Some(since),
+            )),
+            Err(_) => Err(ParserError::UnknownArguments),
+        },
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn entries(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [partner_key, count] => match count.parse::<u32>() {
+            Ok(count) => Ok(MessageType::ENTRIES(String::from(*partner_key), count)),
+            Err(_) => Err(ParserError::UnknownArguments),
+        },
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn entry(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [from, to, timestamp, kind] => {
+            let timestamp = timestamp
+                .parse::<u64>()
+                .map_err(|_| ParserError::UnknownArguments)?;
+            let kind = kind
+                .parse::<BoopKind>()
+                .map_err(|_| ParserError::UnknownArguments)?;
+
+            Ok(MessageType::ENTRY(HistoryEntry {
+                from: String::from(*from),
+                to: String::from(*to),
+                timestamp,
+                kind,
+            }))
+        }
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn stats(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [partner_key] if key_is_valid(partner_key) => {
+            Ok(MessageType::STATS(String::from(*partner_key)))
+        }
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn counts(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [from, to, total, today, streak, longest_streak] => {
+            let count = |text: &str| {
+                text.parse::<u64>()
+                    .map_err(|_| ParserError::UnknownArguments)
+            };
+            let days = |text: &str| {
+                text.parse::<u32>()
+                    .map_err(|_| ParserError::UnknownArguments)
+            };
+
+            Ok(MessageType::COUNTS(PairCounts {
+                from: String::from(*from),
+                to: String::from(*to),
+                total: count(total)?,
+                today: count(today)?,
+                streak: days(streak)?,
+                longest_streak: days(longest_streak)?,
+            }))
+        }
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn boop_back(args: &[&str]) -> Result<MessageType, ParserError> {
+    let (partner_key, id) = match args {
+        [id] => (None, id),
+        [partner_key, id] => (Some(String::from(*partner_key)), id),
+        _ => return Err(ParserError::UnknownArguments),
+    };
+
+    match id.parse::<u64>() {
+        Ok(id) => Ok(MessageType::BOOPBACK(partner_key, id)),
+        Err(_) => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn cancel(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [id] => match id.parse::<u64>() {
+            Ok(id) => Ok(MessageType::CANCEL(id)),
+            Err(_) => Err(ParserError::UnknownArguments),
+        },
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn pending(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [count] => match count.parse::<u32>() {
+            Ok(count) => Ok(MessageType::PENDING(Some(count))),
+            Err(_) => Err(ParserError::UnknownArguments),
+        },
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn scheduled(args: &[&str]) -> Result<MessageType, ParserError> {
+    match args {
+        [id, partner_key, time] => match (id.parse::<u64>(), time.parse::<u64>()) {
+            (Ok(id), Ok(time)) => Ok(MessageType::SCHEDULED(id, String::from(*partner_key), time)),
+            _ => Err(ParserError::UnknownArguments),
+        },
+        _ => Err(ParserError::UnknownArguments),
+    }
+}
+
+fn last_seen(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
-        Ok(MessageType::AFK(String::from(args[0])))
+        Ok(MessageType::LASTSEEN(on_off(args[0])?))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn notice(args: &[&str]) -> Result<MessageType, ParserError> {
+    let text = args.join(" ");
+    if text.is_empty() {
+        Err(ParserError::UnknownArguments)
     } else {
+        Ok(MessageType::NOTICE(text))
+    }
+}
+
+fn banned(args: &[&str]) -> Result<MessageType, ParserError> {
+    let reason = args.join(" ");
+    if reason.is_empty() {
         Err(ParserError::UnknownArguments)
+    } else {
+        Ok(MessageType::BANNED(Some(reason)))
     }
 }
 
-fn error(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn error(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
         match args[0] {
             "NOT_AVAILABLE" => Ok(MessageType::ERROR(MessageErrorKind::NotAvailable)),
             "MALFORMED_COMMAND" => Ok(MessageType::ERROR(MessageErrorKind::MalformedCommand)),
             "MALFORMED_ARGUMENTS" => Ok(MessageType::ERROR(MessageErrorKind::MalformedArguments)),
             "PROTOCOL_MISMATCH" => Ok(MessageType::ERROR(MessageErrorKind::ProtocolMismatch)),
+            "RATE_LIMITED" => Ok(MessageType::ERROR(MessageErrorKind::RateLimited)),
+            "TOO_MANY_CONNECTIONS" => Ok(MessageType::ERROR(MessageErrorKind::TooManyConnections)),
+            "TOO_MANY_SESSIONS" => Ok(MessageType::ERROR(MessageErrorKind::TooManySessions)),
             _ => Err(ParserError::UnknownArguments),
         }
     } else {
@@ -98,32 +590,78 @@ fn error(args: &Vec<&str>) -> Result<MessageType, ParserError> {
 }
 
 fn get_message_type_from_text(cmd: &str, args: Vec<&str>) -> Result<MessageType, ParserError> {
-    if args.len() == 0 {
+    if args.is_empty() {
         match cmd.to_ascii_uppercase().as_str() {
             "DISCONNECT" => Ok(MessageType::DISCONNECT),
             "PING" => Ok(MessageType::PING),
             "HEY" => Ok(MessageType::HEY),
             "NO" => Ok(MessageType::NO),
+            "BANNED" => Ok(MessageType::BANNED(None)),
+            "BLOCKED" => Ok(MessageType::BLOCKED(Vec::new())),
+            "PENDING" => Ok(MessageType::PENDING(None)),
             "PONG" => Ok(MessageType::PONG),
             "BYE" => Ok(MessageType::BYE),
 
             //catch errors
             "CONNECT" => Err(ParserError::UnknownArguments),
+            "REGISTER" => Err(ParserError::UnknownArguments),
             "BOOP" => Err(ParserError::UnknownArguments),
+            "BOOPS" => Err(ParserError::UnknownArguments),
+            "BOOPBACK" => Err(ParserError::UnknownArguments),
             "AYT" => Err(ParserError::UnknownArguments),
+            "DEVICES" => Err(ParserError::UnknownArguments),
+            "STATUS" => Err(ParserError::UnknownArguments),
+            "DND" => Err(ParserError::UnknownArguments),
+            "LASTSEEN" => Err(ParserError::UnknownArguments),
+            "BLOCK" => Err(ParserError::UnknownArguments),
+            "HISTORY" => Err(ParserError::UnknownArguments),
+            "ENTRIES" => Err(ParserError::UnknownArguments),
+            "ENTRY" => Err(ParserError::UnknownArguments),
+            "STATS" => Err(ParserError::UnknownArguments),
+            "COUNTS" => Err(ParserError::UnknownArguments),
+            "CANCEL" => Err(ParserError::UnknownArguments),
+            "SCHEDULED" => Err(ParserError::UnknownArguments),
+            "UNBLOCK" => Err(ParserError::UnknownArguments),
+            "PRESENCE" => Err(ParserError::UnknownArguments),
+            "DEFERRED" => Err(ParserError::UnknownArguments),
             "ERROR" => Err(ParserError::UnknownArguments),
             "ONLINE" => Err(ParserError::UnknownArguments),
             "AFK" => Err(ParserError::UnknownArguments),
+            "NOTICE" => Err(ParserError::UnknownArguments),
+            "KEEPALIVE" => Err(ParserError::UnknownArguments),
             _ => Err(ParserError::UnknownMessageType),
         }
     } else {
         match cmd.to_ascii_uppercase().as_str() {
             "CONNECT" => connect(&args),
+            "REGISTER" => register(&args),
             "BOOP" => boop(&args),
+            "BOOPS" => boops(&args),
+            "BOOPBACK" => boop_back(&args),
             "AYT" => ayt(&args),
+            "DEVICES" => devices(&args),
+            "STATUS" => status(&args),
+            "DND" => dnd(&args),
+            "LASTSEEN" => last_seen(&args),
+            "BLOCK" => block(&args),
+            "UNBLOCK" => unblock(&args),
+            "BLOCKED" => blocked(&args),
+            "HISTORY" => history(&args),
+            "ENTRIES" => entries(&args),
+            "ENTRY" => entry(&args),
+            "STATS" => stats(&args),
+            "COUNTS" => counts(&args),
+            "CANCEL" => cancel(&args),
+            "PENDING" => pending(&args),
+            "SCHEDULED" => scheduled(&args),
+            "PRESENCE" => presence(&args),
+            "DEFERRED" => deferred(&args),
             "ERROR" => error(&args),
             "ONLINE" => online(&args),
             "AFK" => afk(&args),
+            "NOTICE" => notice(&args),
+            "BANNED" => banned(&args),
+            "KEEPALIVE" => keepalive(&args),
 
             // catch errors
             "DISCONNECT" => Err(ParserError::UnknownArguments),
@@ -137,40 +675,112 @@ fn get_message_type_from_text(cmd: &str, args: Vec<&str>) -> Result<MessageType,
     }
 }
 
-pub fn parse_message(msg: &String) -> Result<MessageType, ParserError> {
-    let mut cmd = msg.clone();
+pub fn parse_message(msg: &str) -> Result<MessageType, ParserError> {
+    let mut cmd = String::from(msg);
     // remove newline if it's still at the end
-    if cmd.ends_with("\n") {
+    if cmd.ends_with('\n') {
         cmd.remove(cmd.len() - 1); //remove newline char
     }
     cmd = String::from(cmd.trim());
-    let mut split: Vec<&str> = cmd.split(" ").collect();
+    let mut split: Vec<&str> = cmd.split(' ').collect();
     get_message_type_from_text(split.remove(0), split)
 }
 
 pub fn create_message_text(msg_type: MessageType) -> String {
     match msg_type {
-        MessageType::CONNECT(key, password) => format!("CONNECT {} {}\n", key, password),
+        MessageType::CONNECT(key, password, options) => {
+            format!("CONNECT {} {}{}\n", key, password, options.text())
+        }
+        MessageType::REGISTER(invite, key, password) => {
+            format!("REGISTER {} {} {}\n", invite, key, password)
+        }
         MessageType::DISCONNECT => String::from("DISCONNECT\n"),
         MessageType::PING => String::from("PING\n"),
-        MessageType::BOOP(partner_key) => format!("BOOP {}\n", partner_key),
+        MessageType::BOOP(partner_key, None) => format!("BOOP {}\n", partner_key),
+        MessageType::BOOP(partner_key, Some(id)) => format!("BOOP {} {}\n", partner_key, id),
+        MessageType::SCHEDULE(partner_key, time) => {
+            format!("BOOP {} {}\n", partner_key, time.text())
+        }
+        MessageType::BOOPBACK(None, id) => format!("BOOPBACK {}\n", id),
+        MessageType::BOOPBACK(Some(partner_key), id) => {
+            format!("BOOPBACK {} {}\n", partner_key, id)
+        }
         MessageType::AYT(partner_key) => format!("AYT {}\n", partner_key),
+        MessageType::DEVICES(partner_key) => format!("DEVICES {}\n", partner_key),
+        MessageType::STATUS(state, None) => format!("STATUS {}\n", state.text()),
+        MessageType::STATUS(state, Some(text)) => format!("STATUS {} {}\n", state.text(), text),
+        MessageType::DND(command) => format!("DND {}\n", command.text()),
+        MessageType::LASTSEEN(share) => format!("LASTSEEN {}\n", on_off_text(share)),
+        MessageType::BLOCK(partner_key) => format!("BLOCK {}\n", partner_key),
+        MessageType::UNBLOCK(partner_key) => format!("UNBLOCK {}\n", partner_key),
+        MessageType::BLOCKED(keys) if keys.is_empty() => String::from("BLOCKED\n"),
+        MessageType::BLOCKED(keys) => format!("BLOCKED {}\n", keys.join(" ")),
+        MessageType::HISTORY(partner_key, None) => format!("HISTORY {}\n", partner_key),
+        MessageType::HISTORY(partner_key, Some(since)) => {
+            format!("HISTORY {} {}\n", partner_key, since)
+        }
+        MessageType::STATS(partner_key) => format!("STATS {}\n", partner_key),
+        MessageType::CANCEL(id) => format!("CANCEL {}\n", id),
+        MessageType::PENDING(None) => String::from("PENDING\n"),
         MessageType::HEY => String::from("HEY\n"),
         MessageType::NO => String::from("NO\n"),
+        MessageType::BANNED(None) => String::from("BANNED\n"),
+        MessageType::BANNED(Some(reason)) => format!("BANNED {}\n", reason),
         MessageType::BYE => String::from("BYE\n"),
         MessageType::PONG => String::from("PONG\n"),
         MessageType::ERROR(err_kind) => format!("ERROR {}\n", error_text(err_kind)),
-        MessageType::ONLINE(partner_key) => format!("ONLINE {}\n", partner_key),
-        MessageType::AFK(partner_key) => format!("AFK {}\n", partner_key),
+        MessageType::ONLINE(partner_key, devices) if devices.is_empty() => {
+            format!("ONLINE {}\n", partner_key)
+        }
+        MessageType::ONLINE(partner_key, devices) => {
+            format!("ONLINE {} {}\n", partner_key, devices.join(" "))
+        }
+        MessageType::BOOPS(partner_key, count) => format!("BOOPS {} {}\n", partner_key, count),
+        MessageType::AFK(partner_key, None) => format!("AFK {}\n", partner_key),
+        MessageType::AFK(partner_key, Some(last_seen)) => {
+            format!("AFK {} {}\n", partner_key, last_seen)
+        }
+        MessageType::NOTICE(text) => format!("NOTICE {}\n", text),
+        MessageType::KEEPALIVE(secs) => format!("KEEPALIVE {}\n", secs),
+        MessageType::PRESENCE(partner_key, state, None) => {
+            format!("PRESENCE {} {}\n", partner_key, state.text())
+        }
+        MessageType::PRESENCE(partner_key, state, Some(text)) => {
+            format!("PRESENCE {} {} {}\n", partner_key, state.text(), text)
+        }
+        MessageType::DEFERRED(partner_key) => format!("DEFERRED {}\n", partner_key),
+        MessageType::ENTRIES(partner_key, count) => {
+            format!("ENTRIES {} {}\n", partner_key, count)
+        }
+        MessageType::ENTRY(entry) => format!(
+            "ENTRY {} {} {} {}\n",
+            entry.from, entry.to, entry.timestamp, entry.kind
+        ),
+        MessageType::PENDING(Some(count)) => format!("PENDING {}\n", count),
+        MessageType::SCHEDULED(id, partner_key, time) => {
+            format!("SCHEDULED {} {} {}\n", id, partner_key, time)
+        }
+        MessageType::COUNTS(counts) => format!(
+            "COUNTS {} {} {} {} {} {}\n",
+            counts.from,
+            counts.to,
+            counts.total,
+            counts.today,
+            counts.streak,
+            counts.longest_streak
+        ),
     }
 }
 
-fn error_text(err_kind: MessageErrorKind) -> String {
+pub fn error_text(err_kind: MessageErrorKind) -> String {
     let kind_text = match err_kind {
         MessageErrorKind::NotAvailable => "NOT_AVAILABLE",
         MessageErrorKind::MalformedCommand => "MALFORMED_COMMAND",
         MessageErrorKind::MalformedArguments => "MALFORMED_ARGUMENTS",
         MessageErrorKind::ProtocolMismatch => "PROTOCOL_MISMATCH",
+        MessageErrorKind::RateLimited => "RATE_LIMITED",
+        MessageErrorKind::TooManyConnections => "TOO_MANY_CONNECTIONS",
+        MessageErrorKind::TooManySessions => "TOO_MANY_SESSIONS",
     };
 
     String::from(kind_text)
@@ -184,7 +794,12 @@ fn error_text(err_kind: MessageErrorKind) -> String {
 
 #[cfg(test)]
 mod tests {
-    use crate::message::{parse_message, MessageType, ParserError};
+    use crate::history::{BoopKind, HistoryEntry};
+    use crate::message::{
+        create_message_text, parse_message, BoopTime, DndCommand, LoginOptions, MessageType,
+        ParserError, PresenceState,
+    };
+    use crate::pairstats::PairCounts;
 
     #[test]
     fn test_parser_correct() {
@@ -194,14 +809,43 @@ mod tests {
         assert!(test_res.is_ok());
         assert_eq!(
             test_res.unwrap(),
-            MessageType::CONNECT(String::from("foo"), String::from("bar"))
+            MessageType::CONNECT(
+                String::from("foo"),
+                String::from("bar"),
+                LoginOptions::default()
+            )
+        );
+
+        //three values
+        let teststring = String::from("REGISTER code foo bar\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::REGISTER(
+                String::from("code"),
+                String::from("foo"),
+                String::from("bar")
+            )
         );
 
         //one value
         let teststring = String::from("BOOP foo\n");
         let test_res = parse_message(&teststring);
         assert!(test_res.is_ok());
-        assert_eq!(test_res.unwrap(), MessageType::BOOP(String::from("foo")));
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::BOOP(String::from("foo"), None)
+        );
+
+        //two values, number
+        let teststring = String::from("BOOPS foo 3\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::BOOPS(String::from("foo"), 3)
+        );
 
         //no values
         let teststring = String::from("PING\n");
@@ -209,22 +853,367 @@ mod tests {
         assert!(test_res.is_ok());
         assert_eq!(test_res.unwrap(), MessageType::PING);
 
+        //free text
+        let teststring = String::from("NOTICE relay restarts in  5 minutes\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::NOTICE(String::from("relay restarts in  5 minutes"))
+        );
+
+        //optional free text
+        let teststring = String::from("BANNED\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(test_res.unwrap(), MessageType::BANNED(None));
+
+        let teststring = String::from("BANNED too many boops\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::BANNED(Some(String::from("too many boops")))
+        );
+
         //change case
         let teststring = String::from("coNnECt foo bar\n");
         let test_res = parse_message(&teststring);
         assert!(test_res.is_ok());
         assert_eq!(
             test_res.unwrap(),
-            MessageType::CONNECT(String::from("foo"), String::from("bar"))
+            MessageType::CONNECT(
+                String::from("foo"),
+                String::from("bar"),
+                LoginOptions::default()
+            )
+        );
+
+        //login options
+        let teststring = String::from("CONNECT foo bar keepalive=120\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::CONNECT(
+                String::from("foo"),
+                String::from("bar"),
+                LoginOptions {
+                    keepalive: Some(120),
+                    ..LoginOptions::default()
+                }
+            )
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("CONNECT foo bar device=phone keepalive=120\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::CONNECT(
+                String::from("foo"),
+                String::from("bar"),
+                LoginOptions {
+                    keepalive: Some(120),
+                    device: Some(String::from("phone")),
+                    ..LoginOptions::default()
+                }
+            )
+        );
+        assert_eq!(
+            create_message_text(msg),
+            "CONNECT foo bar keepalive=120 device=phone\n"
         );
 
+        let teststring = String::from("CONNECT foo bar presence=on ids=on\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::CONNECT(
+                String::from("foo"),
+                String::from("bar"),
+                LoginOptions {
+                    presence: true,
+                    ids: true,
+                    ..LoginOptions::default()
+                }
+            )
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        //device list
+        let teststring = String::from("ONLINE foo laptop phone\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::ONLINE(
+                String::from("foo"),
+                vec![String::from("laptop"), String::from("phone")]
+            )
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("ONLINE foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::ONLINE(String::from("foo"), Vec::new())
+        );
+
+        //presence
+        let teststring = String::from("STATUS busy in a meeting\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::STATUS(PresenceState::Busy, Some(String::from("in a meeting")))
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("STATUS DND\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::STATUS(PresenceState::DoNotDisturb, None)
+        );
+
+        let teststring = String::from("PRESENCE foo away back at 5\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::PRESENCE(
+                String::from("foo"),
+                PresenceState::Away,
+                Some(String::from("back at 5"))
+            )
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        //do not disturb
+        let teststring = String::from("DND ON\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::DND(DndCommand::Enabled(true)));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("DND QUIET 22:00-07:00 Europe/Berlin\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::DND(DndCommand::QuietHours(Some(
+                "22:00-07:00 Europe/Berlin".parse().unwrap()
+            )))
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("dnd quiet off\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::DND(DndCommand::QuietHours(None))
+        );
+
+        let teststring = String::from("DND NOTIFY ON\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::DND(DndCommand::NotifySenders(true)));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("DEFERRED foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::DEFERRED(String::from("foo"))
+        );
+
+        //last seen
+        let teststring = String::from("AFK foo 1656676800\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::AFK(String::from("foo"), Some(1656676800)));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("LASTSEEN OFF\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::LASTSEEN(false));
+        assert_eq!(create_message_text(msg), teststring);
+
+        //block list
+        let teststring = String::from("BLOCK foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::BLOCK(String::from("foo")));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("BLOCKED\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(test_res.unwrap(), MessageType::BLOCKED(Vec::new()));
+
+        let teststring = String::from("BLOCKED foo bar\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::BLOCKED(vec![String::from("foo"), String::from("bar")])
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        //history
+        let teststring = String::from("HISTORY foo 1656676800\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::HISTORY(String::from("foo"), Some(1656676800))
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("ENTRY foo bar 1656676800 deferred\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::ENTRY(HistoryEntry {
+                from: String::from("foo"),
+                to: String::from("bar"),
+                timestamp: 1656676800,
+                kind: BoopKind::Deferred
+            })
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        //scheduled boops
+        let teststring = String::from("BOOP foo AT 1656676800\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::SCHEDULE(String::from("foo"), BoopTime::At(1656676800))
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("BOOP foo/phone in 3600\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::SCHEDULE(String::from("foo/phone"), BoopTime::In(3600))
+        );
+
+        let teststring = String::from("PENDING\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::PENDING(None));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("SCHEDULED 7 foo 1656676800\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::SCHEDULED(7, String::from("foo"), 1656676800)
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("CANCEL 7\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::CANCEL(7));
+        assert_eq!(create_message_text(msg), teststring);
+
+        //boop ids and boop-backs
+        let teststring = String::from("BOOP foo 17\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::BOOP(String::from("foo"), Some(17)));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("BOOPBACK 17\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::BOOPBACK(None, 17));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("BOOPBACK foo 17\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::BOOPBACK(Some(String::from("foo")), 17));
+        assert_eq!(create_message_text(msg), teststring);
+
+        //pair stats
+        let teststring = String::from("STATS foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(msg, MessageType::STATS(String::from("foo")));
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("COUNTS foo bar 42 3 5 12\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        let msg = test_res.unwrap();
+        assert_eq!(
+            msg,
+            MessageType::COUNTS(PairCounts {
+                from: String::from("foo"),
+                to: String::from("bar"),
+                total: 42,
+                today: 3,
+                streak: 5,
+                longest_streak: 12
+            })
+        );
+        assert_eq!(create_message_text(msg), teststring);
+
+        let teststring = String::from("KEEPALIVE 120\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(test_res.unwrap(), MessageType::KEEPALIVE(120));
+
         //no newline char
         let teststring = String::from("coNnECt foo bar");
         let test_res = parse_message(&teststring);
         assert!(test_res.is_ok());
         assert_eq!(
             test_res.unwrap(),
-            MessageType::CONNECT(String::from("foo"), String::from("bar"))
+            MessageType::CONNECT(
+                String::from("foo"),
+                String::from("bar"),
+                LoginOptions::default()
+            )
         );
     }
 
@@ -248,6 +1237,18 @@ mod tests {
         assert!(test_res.is_err());
         assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
 
+        //missing arguments / 3
+        let teststring = String::from("REGISTER code foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //empty argument, e.g. a password shifted by a double space
+        let teststring = String::from("REGISTER code  foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
         //too many arguments / 1
         let teststring = String::from("BOOP foo bar\n");
         let test_res = parse_message(&teststring);
@@ -272,6 +1273,141 @@ mod tests {
         assert!(test_res.is_err());
         assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
 
+        //invalid count
+        let teststring = String::from("BOOPS foo many\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("BOOPS foo 0\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid history
+        let teststring = String::from("HISTORY foo yesterday\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("ENTRY foo bar 1656676800 poke\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid scheduled boops
+        let teststring = String::from("BOOP foo AT tomorrow\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("BOOP foo ON 1656676800\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("CANCEL\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid boop ids
+        let teststring = String::from("BOOP foo bar\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("BOOPBACK\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("BOOPBACK foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid pair stats
+        let teststring = String::from("STATS foo/phone\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("COUNTS foo bar 42 3 5\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid block list
+        let teststring = String::from("UNBLOCK foo/phone\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid last seen
+        let teststring = String::from("AFK foo yesterday\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid do not disturb settings
+        let teststring = String::from("DND MAYBE\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("DND QUIET 22:00 Europe/Berlin\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("DND NOTIFY\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid login options
+        let teststring = String::from("CONNECT foo bar keepalive=soon\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("CONNECT foo bar device=my/phone\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("CONNECT foo bar presence=yes\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("CONNECT foo bar color=blue\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("CONNECT foo bar keepalive=60 keepalive=90\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        //invalid presence
+        let teststring = String::from("STATUS sleepy\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = format!("STATUS busy {}\n", "a".repeat(101));
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("PRESENCE foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
         //empty arguments / 1
         let teststring = String::from("BOOP  \n");
         let test_res = parse_message(&teststring);
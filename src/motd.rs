@@ -0,0 +1,72 @@
+use std::{
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use tokio::{fs, sync::RwLock};
+
+/// Message of the day, sent as one notice per line right after a successful login.
+pub struct Motd {
+    path: Option<PathBuf>,
+    lines: RwLock<Vec<String>>,
+}
+
+impl Motd {
+    pub async fn load(path: Option<&Path>) -> Result<Motd, Error> {
+        let motd = Motd {
+            path: path.map(Path::to_path_buf),
+            lines: RwLock::new(Vec::new()),
+        };
+        motd.reload().await?;
+
+        Ok(motd)
+    }
+
+    /// Re-reads the motd file. Returns the new number of lines.
+    pub async fn reload(&self) -> Result<usize, Error> {
+        let lines = match &self.path {
+            Some(path) => motd_lines(&fs::read_to_string(path).await?),
+            None => Vec::new(),
+        };
+        let count = lines.len();
+        *self.lines.write().await = lines;
+
+        Ok(count)
+    }
+
+    pub async fn lines(&self) -> Vec<String> {
+        self.lines.read().await.clone()
+    }
+}
+
+/// Splits the motd text into notice texts, skipping empty lines.
+pub fn motd_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::motd_lines;
+
+    #[test]
+    fn test_motd_lines() {
+        assert_eq!(
+            motd_lines("welcome to the relay!\r\n\n  maintenance on sunday  \n"),
+            vec![
+                String::from("welcome to the relay!"),
+                String::from("maintenance on sunday")
+            ]
+        );
+        assert!(motd_lines("\n \n").is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+use crate::{message::MessageType, stats::Stats};
+
+/// What to do when a message is sent to a connection whose queue is full.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued message.
+    DropOldest,
+    /// Merge a boop into a queued boop from the same sender (`BOOPS <source> <count>`),
+    /// drop the oldest queued message if there is none.
+    Coalesce,
+    /// Close the connection of the slow client.
+    Disconnect,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> QueueConfig {
+        QueueConfig {
+            capacity: 64,
+            policy: OverflowPolicy::Coalesce,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Closed;
+
+struct Inner {
+    queue: VecDeque<MessageType>,
+    senders: usize,
+    overflowed: bool,
+}
+
+struct Shared {
+    config: QueueConfig,
+    inner: Mutex<Inner>,
+    notify: Notify,
+    stats: Arc<Stats>,
+}
+
+/// Transmit half of a connection's bounded message queue.
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+/// Receive half of a connection's bounded message queue.
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+pub fn channel(config: &QueueConfig, stats: Arc<Stats>) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        config: config.clone(),
+        inner: Mutex::new(Inner {
+            queue: VecDeque::with_capacity(config.capacity),
+            senders: 1,
+            overflowed: false,
+        }),
+        notify: Notify::new(),
+        stats,
+    });
+
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+impl Sender {
+    /// Queues a message, applying the overflow policy if the queue is full.
+    pub fn send(&self, msg: MessageType) -> Result<(), Closed> {
+        let shared = &self.shared;
+        let mut inner = shared.inner.lock().unwrap();
+        if inner.overflowed {
+            return Err(Closed);
+        }
+
+        if inner.queue.len() >= shared.config.capacity.max(1) {
+            match shared.config.policy {
+                OverflowPolicy::Coalesce if coalesce(&mut inner.queue, &msg) => {
+                    Stats::increment(&shared.stats.coalesced_messages);
+                    return Ok(());
+                }
+                OverflowPolicy::DropOldest | OverflowPolicy::Coalesce => {
+                    inner.queue.pop_front();
+                    Stats::increment(&shared.stats.dropped_messages);
+                }
+                OverflowPolicy::Disconnect => {
+                    inner.queue.clear();
+                    inner.overflowed = true;
+                    Stats::increment(&shared.stats.slow_client_disconnects);
+                    drop(inner);
+
+                    shared.notify.notify_one();
+                    return Err(Closed);
+                }
+            }
+        }
+
+        inner.queue.push_back(msg);
+        drop(inner);
+
+        shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+/// Merges a boop into a queued boop from the same sender. Returns `false` if there is none.
+fn coalesce(queue: &mut VecDeque<MessageType>, msg: &MessageType) -> bool {
+    let source = match msg {
+        MessageType::BOOP(source, _) => source,
+        _ => return false,
+    };
+
+    for queued in queue.iter_mut().rev() {
+        match queued {
+            MessageType::BOOP(queued_source, _) if queued_source == source => {
+                *queued = MessageType::BOOPS(source.clone(), 2);
+                return true;
+            }
+            MessageType::BOOPS(queued_source, count) if queued_source == source => {
+                *count = count.saturating_add(1);
+                return true;
+            }
+            _ => {}
+        }
+    }
+
+    false
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Sender {
+        self.shared.inner.lock().unwrap().senders += 1;
+
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.inner.lock().unwrap().senders -= 1;
+        self.shared.notify.notify_one();
+    }
+}
+
+impl Receiver {
+    /// Waits for the next message. Returns `None` once all senders are gone (the connection
+    /// was removed from the state) or the queue overflowed with the disconnect policy.
+    pub async fn recv(&mut self) -> Option<MessageType> {
+        loop {
+            {
+                let mut inner = self.shared.inner.lock().unwrap();
+                if inner.overflowed {
+                    return None;
+                }
+                if let Some(msg) = inner.queue.pop_front() {
+                    return Some(msg);
+                }
+                if inner.senders == 0 {
+                    return None;
+                }
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// Whether the queue was closed because the client couldn't keep up.
+    pub fn overflowed(&self) -> bool {
+        self.shared.inner.lock().unwrap().overflowed
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Arc};
+
+    use super::{channel, Closed, OverflowPolicy, QueueConfig};
+    use crate::{message::MessageType, stats::Stats};
+
+    fn config(capacity: usize, policy: OverflowPolicy) -> QueueConfig {
+        QueueConfig { capacity, policy }
+    }
+
+    fn boop(source: &str) -> MessageType {
+        MessageType::BOOP(String::from(source), None)
+    }
+
+    #[tokio::test]
+    async fn test_outbox_order_and_close() {
+        let (tx, mut rx) = channel(
+            &config(4, OverflowPolicy::DropOldest),
+            Arc::new(Stats::new()),
+        );
+        let tx2 = tx.clone();
+
+        tx.send(boop("foo")).unwrap();
+        tx2.send(boop("bar")).unwrap();
+        drop(tx);
+
+        assert_eq!(rx.recv().await, Some(boop("foo")));
+        assert_eq!(rx.recv().await, Some(boop("bar")));
+
+        // queued messages are delivered before the queue reports being closed
+        tx2.send(MessageType::PING).unwrap();
+        drop(tx2);
+        assert_eq!(rx.recv().await, Some(MessageType::PING));
+        assert_eq!(rx.recv().await, None);
+        assert!(!rx.overflowed());
+    }
+
+    #[tokio::test]
+    async fn test_outbox_wakes_receiver() {
+        let (tx, mut rx) = channel(&QueueConfig::default(), Arc::new(Stats::new()));
+
+        let receiver = tokio::spawn(async move { rx.recv().await });
+        tokio::task::yield_now().await;
+        tx.send(boop("foo")).unwrap();
+
+        assert_eq!(receiver.await.unwrap(), Some(boop("foo")));
+    }
+
+    #[tokio::test]
+    async fn test_outbox_drop_oldest() {
+        let stats = Arc::new(Stats::new());
+        let (tx, mut rx) = channel(&config(2, OverflowPolicy::DropOldest), Arc::clone(&stats));
+
+        tx.send(boop("a")).unwrap();
+        tx.send(boop("b")).unwrap();
+        tx.send(boop("c")).unwrap();
+
+        assert_eq!(rx.recv().await, Some(boop("b")));
+        assert_eq!(rx.recv().await, Some(boop("c")));
+        assert_eq!(stats.dropped_messages.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_coalesce() {
+        let stats = Arc::new(Stats::new());
+        let (tx, mut rx) = channel(&config(2, OverflowPolicy::Coalesce), Arc::clone(&stats));
+
+        tx.send(boop("a")).unwrap();
+        tx.send(boop("b")).unwrap();
+        tx.send(boop("a")).unwrap();
+        tx.send(boop("a")).unwrap();
+        tx.send(boop("b")).unwrap();
+
+        assert_eq!(
+            rx.recv().await,
+            Some(MessageType::BOOPS(String::from("a"), 3))
+        );
+        assert_eq!(
+            rx.recv().await,
+            Some(MessageType::BOOPS(String::from("b"), 2))
+        );
+        assert_eq!(stats.coalesced_messages.load(Ordering::Relaxed), 3);
+
+        // messages that can't be merged fall back to dropping the oldest message
+        tx.send(boop("a")).unwrap();
+        tx.send(boop("b")).unwrap();
+        tx.send(MessageType::NOTICE(String::from("hi"))).unwrap();
+
+        assert_eq!(rx.recv().await, Some(boop("b")));
+        assert_eq!(stats.dropped_messages.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_disconnect() {
+        let stats = Arc::new(Stats::new());
+        let (tx, mut rx) = channel(&config(1, OverflowPolicy::Disconnect), Arc::clone(&stats));
+
+        tx.send(boop("a")).unwrap();
+        assert_eq!(tx.send(boop("b")), Err(Closed));
+        assert_eq!(tx.send(boop("c")), Err(Closed));
+
+        assert_eq!(rx.recv().await, None);
+        assert!(rx.overflowed());
+        assert_eq!(stats.slow_client_disconnects.load(Ordering::Relaxed), 1);
+    }
+}
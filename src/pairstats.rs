@@ -0,0 +1,305 @@
+use std::{
+    collections::BTreeMap,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Boop counters of a sender towards a recipient. Days are UTC days.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PairStats {
+    pub total: u64,
+    /// Boops on `last_day`.
+    pub day_count: u64,
+    /// Days since the unix epoch of the last boop.
+    pub last_day: u64,
+    /// Days in a row with at least one boop, up to `last_day`.
+    pub streak: u32,
+    pub longest_streak: u32,
+}
+
+impl PairStats {
+    pub fn record(&mut self, count: u64, now: u64) {
+        let day = now / SECONDS_PER_DAY;
+
+        if self.total > 0 && day == self.last_day {
+            self.day_count += count;
+        } else {
+            self.streak = if self.total > 0 && day == self.last_day + 1 {
+                self.streak.saturating_add(1)
+            } else {
+                1
+            };
+            self.day_count = count;
+            self.last_day = day;
+        }
+        self.total += count;
+        self.longest_streak = self.longest_streak.max(self.streak);
+    }
+
+    /// Counters as of `now`, today's boops and the current streak are 0 once they're over.
+    pub fn counts(&self, from: &str, to: &str, now: u64) -> PairCounts {
+        let day = now / SECONDS_PER_DAY;
+
+        PairCounts {
+            from: String::from(from),
+            to: String::from(to),
+            total: self.total,
+            today: if day == self.last_day {
+                self.day_count
+            } else {
+                0
+            },
+            streak: if day <= self.last_day + 1 {
+                self.streak
+            } else {
+                0
+            },
+            longest_streak: self.longest_streak,
+        }
+    }
+}
+
+/// The counters of a pair as sent to clients and the admin interface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairCounts {
+    pub from: String,
+    pub to: String,
+    pub total: u64,
+    pub today: u64,
+    pub streak: u32,
+    pub longest_streak: u32,
+}
+
+/// Boop counters per sender and recipient, backed by the pair stats file. The counters are
+/// kept in memory and written by [`flush`](PairStatsStore::flush), so delivering a boop never
+/// waits for the file.
+pub struct PairStatsStore {
+    path: PathBuf,
+    pairs: Mutex<Pairs>,
+    // held while writing so the file is written in order
+    writing: Mutex<()>,
+}
+
+struct Pairs {
+    // sender -> recipient -> stats
+    stats: BTreeMap<String, BTreeMap<String, PairStats>>,
+    // changed since the last write
+    dirty: bool,
+    // no more counting after the relay shut down
+    closed: bool,
+}
+
+impl PairStatsStore {
+    /// Reads the pair stats file, a missing file is an empty store.
+    pub async fn open(path: &Path) -> Result<PairStatsStore, Error> {
+        let stats = read_pair_stats_file(path).await?;
+
+        Ok(PairStatsStore {
+            path: path.to_path_buf(),
+            pairs: Mutex::new(Pairs {
+                stats,
+                dirty: false,
+                closed: false,
+            }),
+            writing: Mutex::new(()),
+        })
+    }
+
+    /// Counts `count` boops from `from` to `to` at `now`, the file is updated by the next
+    /// flush. Does nothing once the store is closed.
+    pub async fn record(&self, from: &str, to: &str, count: u64, now: u64) {
+        let mut pairs = self.pairs.lock().await;
+        if pairs.closed {
+            return;
+        }
+
+        pairs
+            .stats
+            .entry(String::from(from))
+            .or_default()
+            .entry(String::from(to))
+            .or_default()
+            .record(count, now);
+        pairs.dirty = true;
+    }
+
+    /// Writes the counters to the file if they changed since the last flush.
+    pub async fn flush(&self) -> Result<(), Error> {
+        let _writing = self.writing.lock().await;
+
+        let stats = {
+            let mut pairs = self.pairs.lock().await;
+            if !pairs.dirty {
+                return Ok(());
+            }
+            pairs.dirty = false;
+            pairs.stats.clone()
+        };
+
+        let result = write_pair_stats_file(&self.path, &stats).await;
+        if result.is_err() {
+            // try again with the next flush
+            self.pairs.lock().await.dirty = true;
+        }
+        result
+    }
+
+    /// The counters from `from` to `to`, all 0 if `from` never booped `to`.
+    pub async fn get(&self, from: &str, to: &str, now: u64) -> PairCounts {
+        self.pairs
+            .lock()
+            .await
+            .stats
+            .get(from)
+            .and_then(|recipients| recipients.get(to))
+            .cloned()
+            .unwrap_or_default()
+            .counts(from, to, now)
+    }
+
+    /// The counters of all pairs, ordered by sender and recipient.
+    pub async fn all(&self, now: u64) -> Vec<PairCounts> {
+        let pairs = self.pairs.lock().await;
+
+        pairs
+            .stats
+            .iter()
+            .flat_map(|(from, recipients)| {
+                recipients
+                    .iter()
+                    .map(move |(to, stats)| stats.counts(from, to, now))
+            })
+            .collect()
+    }
+
+    /// Ignores later calls to `record`, for the handlers still running at shutdown, and
+    /// writes the last counters.
+    pub async fn close(&self) -> Result<(), Error> {
+        self.pairs.lock().await.closed = true;
+        self.flush().await
+    }
+}
+
+pub async fn read_pair_stats_file(
+    path: &Path,
+) -> Result<BTreeMap<String, BTreeMap<String, PairStats>>, Error> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let contents = fs::read_to_string(path).await?;
+    let stats = serde_json::from_str(contents.as_str())?;
+
+    Ok(stats)
+}
+
+pub async fn write_pair_stats_file(
+    path: &Path,
+    stats: &BTreeMap<String, BTreeMap<String, PairStats>>,
+) -> Result<(), Error> {
+    let contents = serde_json::to_string_pretty(stats)?;
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::{PairCounts, PairStats, PairStatsStore, SECONDS_PER_DAY};
+
+    fn counts(total: u64, today: u64, streak: u32, longest_streak: u32) -> PairCounts {
+        PairCounts {
+            from: String::from("foo"),
+            to: String::from("bar"),
+            total,
+            today,
+            streak,
+            longest_streak,
+        }
+    }
+
+    #[test]
+    fn test_pair_stats_streaks() {
+        let day = 19000 * SECONDS_PER_DAY;
+        let mut stats = PairStats::default();
+        assert_eq!(stats.counts("foo", "bar", day), counts(0, 0, 0, 0));
+
+        stats.record(1, day + 100);
+        stats.record(2, day + 200);
+        assert_eq!(stats.counts("foo", "bar", day + 300), counts(3, 3, 1, 1));
+
+        // the next two days continue the streak
+        stats.record(1, day + SECONDS_PER_DAY);
+        stats.record(1, day + 2 * SECONDS_PER_DAY);
+        assert_eq!(
+            stats.counts("foo", "bar", day + 2 * SECONDS_PER_DAY),
+            counts(5, 1, 3, 3)
+        );
+        // still running on the next day, over after that
+        assert_eq!(
+            stats.counts("foo", "bar", day + 3 * SECONDS_PER_DAY),
+            counts(5, 0, 3, 3)
+        );
+        assert_eq!(
+            stats.counts("foo", "bar", day + 4 * SECONDS_PER_DAY),
+            counts(5, 0, 0, 3)
+        );
+
+        // a missed day starts a new streak, the longest one is kept
+        stats.record(1, day + 4 * SECONDS_PER_DAY);
+        assert_eq!(
+            stats.counts("foo", "bar", day + 4 * SECONDS_PER_DAY),
+            counts(6, 1, 1, 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pair_stats_persisted() {
+        let path =
+            std::env::temp_dir().join(format!("boop-pair-stats-{}.json", uuid::Uuid::new_v4()));
+        let day = 19000 * SECONDS_PER_DAY;
+
+        let store = PairStatsStore::open(&path).await.unwrap();
+        store.record("foo", "bar", 2, day).await;
+        store.record("bar", "foo", 1, day).await;
+        assert!(!path.exists());
+        store.flush().await.unwrap();
+
+        let store = PairStatsStore::open(&path).await.unwrap();
+        assert_eq!(store.get("foo", "bar", day).await, counts(2, 2, 1, 1));
+        assert_eq!(store.get("foo", "baz", day).await.total, 0);
+        assert_eq!(
+            store
+                .all(day)
+                .await
+                .into_iter()
+                .map(|counts| (counts.from, counts.to))
+                .collect::<Vec<_>>(),
+            vec![
+                (String::from("bar"), String::from("foo")),
+                (String::from("foo"), String::from("bar"))
+            ]
+        );
+
+        store.close().await.unwrap();
+        store.record("foo", "bar", 1, day).await;
+        store.flush().await.unwrap();
+        let store = PairStatsStore::open(&path).await.unwrap();
+        assert_eq!(store.get("foo", "bar", day).await.total, 2);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Error},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+};
+
+/*
+    PROXY protocol (https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt), sent by
+    load balancers in front of the actual stream:
+
+    v1: `PROXY TCP4 <src ip> <dst ip> <src port> <dst port>\r\n` (or TCP6 / UNKNOWN)
+    v2: 12 byte signature, version/command, family, length (u16), addresses, TLVs
+*/
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const HEADER_TIMEOUT_SECS: u64 = 5;
+
+/// Address block of an IPv4 / IPv6 v2 header: addresses and ports of source and destination.
+const V2_INET_LENGTH: usize = 12;
+const V2_INET6_LENGTH: usize = 36;
+
+/// An IP network like `10.0.0.0/8` or `fd00::/8`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full_bytes = usize::from(prefix / 8);
+    let rest_bits = prefix % 8;
+
+    if net[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - rest_bits);
+    net[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Cidr, String> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid network address: {}", text))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid network prefix: {}", text))?,
+            None => max_prefix,
+        };
+
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Cidr, String> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// PROXY protocol is enabled if at least one trusted proxy is configured. Connections from
+/// trusted proxies must start with a PROXY header, others are handled as direct connections.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl ProxyConfig {
+    pub(crate) fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns the address of the actual client: the source address of the PROXY header for
+/// connections from trusted proxies, the peer address otherwise. Consumes exactly the header,
+/// so the TLS handshake or the first command can follow.
+pub(crate) async fn real_peer_addr(
+    stream: &mut TcpStream,
+    peer_addr: SocketAddr,
+    config: &ProxyConfig,
+) -> io::Result<SocketAddr> {
+    // other peers are handled as direct connections, a spoofed header is just garbage
+    // in front of the TLS handshake or the first command
+    if !config.is_trusted(peer_addr.ip()) {
+        return Ok(peer_addr);
+    }
+
+    let header = tokio::time::timeout(
+        Duration::from_secs(HEADER_TIMEOUT_SECS),
+        read_header(stream),
+    )
+    .await
+    .map_err(|_| Error::new(io::ErrorKind::TimedOut, "no PROXY header received"))??;
+
+    Ok(header.unwrap_or(peer_addr))
+}
+
+/// Reads a v1 or v2 header. Returns `None` if the header doesn't carry a client address
+/// (e.g. health checks of the proxy).
+async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Option<SocketAddr>> {
+    // the shortest v1 header (`PROXY UNKNOWN\r\n`) is longer than the v2 signature
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        let length = usize::from(u16::from_be_bytes([head[2], head[3]]));
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).await?;
+
+        parse_v2(head[0], head[1], &body)
+    } else if start.starts_with(V1_PREFIX) {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("PROXY header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+
+        let line = std::str::from_utf8(&line).map_err(|_| invalid("malformed PROXY header"))?;
+        parse_v1(line)
+    } else {
+        Err(invalid("missing PROXY header"))
+    }
+}
+
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", protocol @ ("TCP4" | "TCP6"), src_ip, _dst_ip, src_port, _dst_port] => {
+            let ip: IpAddr = src_ip
+                .parse()
+                .map_err(|_| invalid("malformed PROXY source address"))?;
+            let port: u16 = src_port
+                .parse()
+                .map_err(|_| invalid("malformed PROXY source port"))?;
+            if ip.is_ipv4() != (*protocol == "TCP4") {
+                return Err(invalid("PROXY address doesn't match the protocol"));
+            }
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed PROXY header")),
+    }
+}
+
+fn parse_v2(version_command: u8, family: u8, body: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    match version_command & 0x0f {
+        0x0 => return Ok(None), // LOCAL: connection of the proxy itself
+        0x1 => {}               // PROXY
+        _ => return Err(invalid("unsupported PROXY command")),
+    }
+
+    match family >> 4 {
+        0x1 if body.len() >= V2_INET_LENGTH => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        0x2 if body.len() >= V2_INET6_LENGTH => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        0x1 | 0x2 => Err(invalid("truncated PROXY addresses")),
+        _ => Ok(None), // UNSPEC or unix socket addresses
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{parse_v1, read_header, Cidr, ProxyConfig, V2_SIGNATURE};
+
+    fn addr(text: &str) -> Option<SocketAddr> {
+        Some(text.parse().unwrap())
+    }
+
+    #[test]
+    fn test_proxy_v1() {
+        assert_eq!(
+            parse_v1("PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n").unwrap(),
+            addr("192.168.0.1:56324")
+        );
+        assert_eq!(
+            parse_v1("PROXY TCP6 2001:db8::1 2001:db8::2 4711 443\r\n").unwrap(),
+            addr("[2001:db8::1]:4711")
+        );
+        assert_eq!(parse_v1("PROXY UNKNOWN\r\n").unwrap(), None);
+
+        assert!(parse_v1("PROXY TCP4 2001:db8::1 2001:db8::2 4711 443\r\n").is_err());
+        assert!(parse_v1("PROXY TCP4 192.168.0.1 192.168.0.11 99999 443\r\n").is_err());
+        assert!(parse_v1("PROXY TCP4 192.168.0.1\r\n").is_err());
+        assert!(parse_v1("PROXY UDP4 192.168.0.1 192.168.0.11 56324 443\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]); // v2 PROXY, TCP over IPv4
+        header.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x01, 0xbb]);
+        header.extend_from_slice(b"CONNECT foo bar\n");
+
+        let mut stream = cursor(header);
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            addr("10.0.0.1:8080")
+        );
+        // the stream continues right after the header
+        assert_eq!(stream.get_ref().len() - stream.position() as usize, 16);
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]); // v2 LOCAL
+        assert_eq!(read_header(&mut cursor(local)).await.unwrap(), None);
+
+        let mut truncated = V2_SIGNATURE.to_vec();
+        truncated.extend_from_slice(&[0x21, 0x11, 0, 4, 10, 0, 0, 1]);
+        assert!(read_header(&mut cursor(truncated)).await.is_err());
+
+        let missing = b"CONNECT foo bar\n".to_vec();
+        assert!(read_header(&mut cursor(missing)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_v1_stream() {
+        let header = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nCONNECT foo bar\n".to_vec();
+        let mut stream = cursor(header);
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            addr("192.168.0.1:56324")
+        );
+        assert_eq!(stream.get_ref().len() - stream.position() as usize, 16);
+
+        let endless = [b"PROXY ".as_slice(), &[b'1'; 200]].concat();
+        assert!(read_header(&mut cursor(endless)).await.is_err());
+    }
+
+    fn cursor(bytes: Vec<u8>) -> std::io::Cursor<Vec<u8>> {
+        std::io::Cursor::new(bytes)
+    }
+
+    #[test]
+    fn test_cidr() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains("10.1.2.3".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+
+        let odd: Cidr = "192.168.4.0/22".parse().unwrap();
+        assert!(odd.contains("192.168.7.255".parse().unwrap()));
+        assert!(!odd.contains("192.168.8.0".parse().unwrap()));
+
+        let host: Cidr = "fd00::1".parse().unwrap();
+        assert!(host.contains("fd00::1".parse().unwrap()));
+        assert!(!host.contains("fd00::2".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("localhost/8".parse::<Cidr>().is_err());
+
+        let config: ProxyConfig =
+            serde_json::from_str(r#"{ "trusted_proxies": ["10.0.0.0/8", "::1"] }"#).unwrap();
+        assert_eq!(config.trusted_proxies.len(), 2);
+        assert!(serde_json::from_str::<ProxyConfig>(r#"{ "trusted_proxies": ["nope"] }"#).is_err());
+    }
+}
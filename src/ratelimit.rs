@@ -0,0 +1,535 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::message::MessageType;
+
+/// Token bucket parameters: `burst` commands at once, refilled with `rate` commands per second.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "LimitConfig")]
+pub struct Limit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitConfig {
+    rate: f64,
+    burst: u32,
+}
+
+impl TryFrom<LimitConfig> for Limit {
+    type Error = String;
+
+    fn try_from(config: LimitConfig) -> Result<Limit, String> {
+        if !config.rate.is_finite() || config.rate <= 0.0 {
+            return Err(format!(
+                "rate must be a positive number, not {}",
+                config.rate
+            ));
+        }
+        if config.burst == 0 {
+            return Err(String::from("burst must be at least 1"));
+        }
+
+        Ok(Limit {
+            rate: config.rate,
+            burst: config.burst,
+        })
+    }
+}
+
+/// Limits per command kind. `None` means unlimited.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandLimits {
+    pub boop: Option<Limit>,
+    pub ayt: Option<Limit>,
+    pub total: Option<Limit>,
+}
+
+/// Rate limiting is off unless configured.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub connection: CommandLimits,
+    pub key: CommandLimits,
+    /// Rejected commands a connection may pile up before it gets disconnected.
+    pub disconnect_after: Option<Limit>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandKind {
+    Boop,
+    Ayt,
+    Other,
+}
+
+impl From<&MessageType> for CommandKind {
+    fn from(msg: &MessageType) -> CommandKind {
+        match msg {
+            MessageType::BOOP(_, _) | MessageType::SCHEDULE(_, _) | MessageType::BOOPBACK(_, _) => {
+                CommandKind::Boop
+            }
+            MessageType::AYT(_) | MessageType::DEVICES(_) => CommandKind::Ayt,
+            _ => CommandKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allowed,
+    Limited,
+    Disconnect,
+}
+
+pub struct TokenBucket {
+    limit: Limit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: Limit, now: Instant) -> TokenBucket {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst as f64);
+        self.updated = now;
+    }
+
+    pub fn has_token(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// Refilled to the burst size, so it's no different from a new bucket.
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.limit.burst as f64
+    }
+
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        if self.has_token(now) {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The buckets of one connection or one key.
+struct CommandBuckets {
+    boop: Option<TokenBucket>,
+    ayt: Option<TokenBucket>,
+    total: Option<TokenBucket>,
+}
+
+impl CommandBuckets {
+    fn new(limits: &CommandLimits, now: Instant) -> CommandBuckets {
+        let bucket = |limit: Option<Limit>| limit.map(|limit| TokenBucket::new(limit, now));
+
+        CommandBuckets {
+            boop: bucket(limits.boop),
+            ayt: bucket(limits.ayt),
+            total: bucket(limits.total),
+        }
+    }
+
+    fn for_kind(&mut self, kind: CommandKind) -> [Option<&mut TokenBucket>; 2] {
+        let specific = match kind {
+            CommandKind::Boop => self.boop.as_mut(),
+            CommandKind::Ayt => self.ayt.as_mut(),
+            CommandKind::Other => None,
+        };
+
+        [specific, self.total.as_mut()]
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        [&mut self.boop, &mut self.ayt, &mut self.total]
+            .into_iter()
+            .flatten()
+            .all(|bucket| bucket.is_full(now))
+    }
+
+    fn allows(&mut self, kind: CommandKind, now: Instant) -> bool {
+        self.for_kind(kind)
+            .into_iter()
+            .flatten()
+            .all(|bucket| bucket.has_token(now))
+    }
+
+    fn consume(&mut self, kind: CommandKind, now: Instant) {
+        for bucket in self.for_kind(kind).into_iter().flatten() {
+            bucket.try_take(now);
+        }
+    }
+}
+
+/// Rate limiting state of a single connection.
+pub struct ConnectionLimiter {
+    buckets: CommandBuckets,
+    strikes: Option<TokenBucket>,
+}
+
+/// Rate limits commands per connection and per key. The per-key buckets are shared by all
+/// connections of a key.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    keys: Mutex<KeyBuckets>,
+}
+
+struct KeyBuckets {
+    buckets: HashMap<String, CommandBuckets>,
+    pruned: Instant,
+}
+
+/// How often the buckets of idle keys are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            config,
+            keys: Mutex::new(KeyBuckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn connection_limiter(&self) -> ConnectionLimiter {
+        let now = Instant::now();
+
+        ConnectionLimiter {
+            buckets: CommandBuckets::new(&self.config.connection, now),
+            strikes: self
+                .config
+                .disconnect_after
+                .map(|limit| TokenBucket::new(limit, now)),
+        }
+    }
+
+    pub fn check(
+        &self,
+        key: &str,
+        connection: &mut ConnectionLimiter,
+        kind: CommandKind,
+    ) -> Verdict {
+        self.check_at(key, connection, kind, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        key: &str,
+        connection: &mut ConnectionLimiter,
+        kind: CommandKind,
+        now: Instant,
+    ) -> Verdict {
+        let mut keys = self.keys.lock().unwrap();
+        if now.saturating_duration_since(keys.pruned) >= PRUNE_INTERVAL {
+            // full buckets are recreated just the same on the next command of the key
+            keys.buckets.retain(|_, buckets| !buckets.is_full(now));
+            keys.pruned = now;
+        }
+        let key_buckets = keys
+            .buckets
+            .entry(String::from(key))
+            .or_insert_with(|| CommandBuckets::new(&self.config.key, now));
+
+        if connection.buckets.allows(kind, now) && key_buckets.allows(kind, now) {
+            connection.buckets.consume(kind, now);
+            key_buckets.consume(kind, now);
+            return Verdict::Allowed;
+        }
+
+        let out_of_strikes = match connection.strikes.as_mut() {
+            Some(strikes) => !strikes.try_take(now),
+            None => false,
+        };
+
+        if out_of_strikes {
+            Verdict::Disconnect
+        } else {
+            Verdict::Limited
+        }
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        CommandKind, CommandLimits, Limit, RateLimitConfig, RateLimiter, TokenBucket, Verdict,
+    };
+
+    const SLOW: Limit = Limit {
+        rate: 1.0,
+        burst: 2,
+    };
+
+    fn config(
+        connection: CommandLimits,
+        key: CommandLimits,
+        disconnect_after: Option<Limit>,
+    ) -> RateLimitConfig {
+        RateLimitConfig {
+            connection,
+            key,
+            disconnect_after,
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(SLOW, start);
+
+        // burst
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+
+        // refill
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+        assert!(bucket.try_take(start + Duration::from_millis(1000)));
+        assert!(!bucket.try_take(start + Duration::from_millis(1000)));
+
+        // refill never exceeds the burst size
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn test_per_command_limits() {
+        let limiter = RateLimiter::new(config(
+            CommandLimits {
+                boop: Some(SLOW),
+                ..Default::default()
+            },
+            CommandLimits::default(),
+            None,
+        ));
+        let mut connection = limiter.connection_limiter();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, now),
+            Verdict::Limited
+        );
+
+        // other commands are not affected by the boop limit
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Ayt, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Other, now),
+            Verdict::Allowed
+        );
+    }
+
+    #[test]
+    fn test_total_limit() {
+        let limiter = RateLimiter::new(config(
+            CommandLimits {
+                total: Some(SLOW),
+                ..Default::default()
+            },
+            CommandLimits::default(),
+            None,
+        ));
+        let mut connection = limiter.connection_limiter();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Ayt, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Other, now),
+            Verdict::Limited
+        );
+    }
+
+    #[test]
+    fn test_key_limit_is_shared() {
+        let limiter = RateLimiter::new(config(
+            CommandLimits::default(),
+            CommandLimits {
+                boop: Some(SLOW),
+                ..Default::default()
+            },
+            None,
+        ));
+        let mut first = limiter.connection_limiter();
+        let mut second = limiter.connection_limiter();
+        let mut other_key = limiter.connection_limiter();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check_at("foo", &mut first, CommandKind::Boop, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut second, CommandKind::Boop, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut first, CommandKind::Boop, now),
+            Verdict::Limited
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut second, CommandKind::Boop, now),
+            Verdict::Limited
+        );
+
+        assert_eq!(
+            limiter.check_at("bar", &mut other_key, CommandKind::Boop, now),
+            Verdict::Allowed
+        );
+    }
+
+    #[test]
+    fn test_limited_commands_are_not_consumed() {
+        // a command rejected by the key limit must not use up the connection limit
+        let limiter = RateLimiter::new(config(
+            CommandLimits {
+                boop: Some(Limit {
+                    rate: 0.001,
+                    burst: 2,
+                }),
+                ..Default::default()
+            },
+            CommandLimits {
+                boop: Some(Limit {
+                    rate: 1.0,
+                    burst: 1,
+                }),
+                ..Default::default()
+            },
+            None,
+        ));
+        let mut connection = limiter.connection_limiter();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, now),
+            Verdict::Limited
+        );
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, later),
+            Verdict::Allowed
+        );
+    }
+
+    #[test]
+    fn test_disconnect_escalation() {
+        let limiter = RateLimiter::new(config(
+            CommandLimits {
+                total: Some(Limit {
+                    rate: 1.0,
+                    burst: 1,
+                }),
+                ..Default::default()
+            },
+            CommandLimits::default(),
+            Some(SLOW),
+        ));
+        let mut connection = limiter.connection_limiter();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Other, now),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Other, now),
+            Verdict::Limited
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Other, now),
+            Verdict::Limited
+        );
+        assert_eq!(
+            limiter.check_at("foo", &mut connection, CommandKind::Other, now),
+            Verdict::Disconnect
+        );
+    }
+
+    #[test]
+    fn test_default_config_is_unlimited() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let mut connection = limiter.connection_limiter();
+        let now = Instant::now();
+
+        assert!((0..1000).all(|_| {
+            limiter.check_at("foo", &mut connection, CommandKind::Boop, now) == Verdict::Allowed
+        }));
+    }
+
+    #[test]
+    fn test_idle_keys_are_pruned() {
+        let limiter = RateLimiter::new(config(
+            CommandLimits::default(),
+            CommandLimits {
+                boop: Some(SLOW),
+                ..Default::default()
+            },
+            None,
+        ));
+        let mut connection = limiter.connection_limiter();
+        let now = Instant::now();
+
+        limiter.check_at("foo", &mut connection, CommandKind::Boop, now);
+        limiter.check_at("bar", &mut connection, CommandKind::Boop, now);
+        assert_eq!(limiter.keys.lock().unwrap().buckets.len(), 2);
+
+        // refilled after a while, so dropping it changes nothing
+        let later = now + Duration::from_secs(61);
+        limiter.check_at("foo", &mut connection, CommandKind::Boop, later);
+        assert_eq!(limiter.keys.lock().unwrap().buckets.len(), 1);
+    }
+}
@@ -0,0 +1,653 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use crate::{
+    message::{MessageType, PresenceState},
+    Tx,
+};
+
+const DEFAULT_SHARDS: usize = 64;
+
+// User-Key -> Connection-ID -> Session
+type Shard = HashMap<String, HashMap<String, Session>>;
+
+struct Session {
+    channel: Tx,
+    /// Name the client gave its device at login.
+    device: Option<String>,
+    /// The client asked for boop IDs at login.
+    boop_ids: bool,
+    /// Registration order, to find the oldest session of a key.
+    seq: u64,
+    status: Status,
+}
+
+/// Presence state and status text of a connection.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub state: PresenceState,
+    pub text: Option<String>,
+    /// When the status was set, newer statuses win ties between devices.
+    seq: u64,
+}
+
+impl Status {
+    pub fn new(state: PresenceState, text: Option<String>) -> Status {
+        Status {
+            state,
+            text,
+            seq: 0,
+        }
+    }
+}
+
+/// The key already has the maximum number of sessions.
+#[derive(Debug, PartialEq)]
+pub struct SessionLimitReached;
+
+/// Presence registry of all logged in connections.
+///
+/// Keys are spread over independently locked shards, so boops and presence checks for
+/// different keys don't wait for each other. Locks are never held across an `.await`.
+pub struct Registry {
+    shards: Vec<RwLock<Shard>>,
+    hasher: RandomState,
+    next_seq: AtomicU64,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::with_shards(DEFAULT_SHARDS)
+    }
+
+    pub fn with_shards(shards: usize) -> Registry {
+        Registry {
+            shards: (0..shards.max(1))
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            hasher: RandomState::new(),
+            next_seq: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, client_key: &str) -> &RwLock<Shard> {
+        let index = self.hasher.hash_one(client_key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    fn session(&self, device: Option<&str>, channel: Tx) -> Session {
+        Session {
+            channel,
+            device: device.map(String::from),
+            boop_ids: false,
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            status: Status::default(),
+        }
+    }
+
+    pub fn register(
+        &self,
+        client_key: &str,
+        connection_id: &str,
+        device: Option<&str>,
+        channel: Tx,
+    ) {
+        let session = self.session(device, channel);
+        self.shard(client_key)
+            .write()
+            .unwrap()
+            .entry(String::from(client_key))
+            .or_default()
+            .insert(String::from(connection_id), session);
+    }
+
+    /// Registers a connection unless the key already has `max_sessions` connections. With
+    /// `evict_oldest`, the oldest connections of the key are removed instead; their IDs and
+    /// channels are returned.
+    pub fn register_limited(
+        &self,
+        client_key: &str,
+        connection_id: &str,
+        device: Option<&str>,
+        channel: Tx,
+        max_sessions: usize,
+        evict_oldest: bool,
+    ) -> Result<Vec<(String, Tx)>, SessionLimitReached> {
+        if max_sessions == 0 {
+            return Err(SessionLimitReached);
+        }
+
+        let session = self.session(device, channel);
+        let mut shard = self.shard(client_key).write().unwrap();
+        let inner_map = shard.entry(String::from(client_key)).or_default();
+        if inner_map.len() >= max_sessions && !evict_oldest {
+            return Err(SessionLimitReached);
+        }
+
+        let mut evicted = Vec::new();
+        while inner_map.len() >= max_sessions {
+            let oldest = inner_map
+                .iter()
+                .min_by_key(|(_, session)| session.seq)
+                .map(|(connection_id, _)| connection_id.clone())
+                .unwrap();
+            let session = inner_map.remove(&oldest).unwrap();
+            evicted.push((oldest, session.channel));
+        }
+        inner_map.insert(String::from(connection_id), session);
+
+        Ok(evicted)
+    }
+
+    pub fn unregister(&self, client_key: &str, connection_id: &str) -> bool {
+        let mut shard = self.shard(client_key).write().unwrap();
+
+        let removed = match shard.get_mut(client_key) {
+            Some(inner_map) => inner_map.remove(connection_id).is_some(),
+            None => false,
+        };
+        if shard.get(client_key).is_some_and(HashMap::is_empty) {
+            shard.remove(client_key);
+        }
+
+        removed
+    }
+
+    // Dropping the channel of a connection makes its handler close the connection.
+
+    /// Removes a connection without knowing its key. Returns `false` if it doesn't exist.
+    pub fn kick_connection(&self, connection_id: &str) -> bool {
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+
+            let client_key = shard
+                .iter()
+                .find(|(_, inner_map)| inner_map.contains_key(connection_id))
+                .map(|(client_key, _)| client_key.clone());
+
+            if let Some(client_key) = client_key {
+                drop(shard);
+                return self.unregister(&client_key, connection_id);
+            }
+        }
+
+        false
+    }
+
+    /// Removes all connections of a key. Returns the number of removed connections.
+    pub fn kick_key(&self, client_key: &str) -> usize {
+        self.shard(client_key)
+            .write()
+            .unwrap()
+            .remove(client_key)
+            .map(|inner_map| inner_map.len())
+            .unwrap_or(0)
+    }
+
+    /// Removes all connections. Returns the number of removed connections.
+    pub fn kick_all(&self) -> usize {
+        let mut kicked = 0;
+        for shard in &self.shards {
+            kicked += shard
+                .write()
+                .unwrap()
+                .drain()
+                .map(|(_, inner_map)| inner_map.len())
+                .sum::<usize>();
+        }
+
+        kicked
+    }
+
+    /// Sends the message to every connection of the key. Returns the number of connections
+    /// that accepted the message.
+    pub fn fan_out(&self, client_key: &str, msg: &MessageType) -> usize {
+        let shard = self.shard(client_key).read().unwrap();
+
+        shard.get(client_key).map_or(0, |inner_map| {
+            inner_map
+                .values()
+                .filter(|session| session.channel.send(msg.clone()).is_ok())
+                .count()
+        })
+    }
+
+    /// Sends a boop to every connection of the key, or only to those that logged in with the
+    /// device name. Connections that asked for boop IDs get `with_id`, which is only called if
+    /// there is such a connection, the others get `plain`. Returns the number of connections
+    /// that accepted the boop.
+    pub fn fan_out_boop(
+        &self,
+        client_key: &str,
+        device: Option<&str>,
+        plain: &MessageType,
+        with_id: impl FnOnce() -> MessageType,
+    ) -> usize {
+        let shard = self.shard(client_key).read().unwrap();
+
+        let sessions: Vec<&Session> = match shard.get(client_key) {
+            Some(inner_map) => inner_map
+                .values()
+                .filter(|session| match device {
+                    Some(device) => session.device.as_deref() == Some(device),
+                    None => true,
+                })
+                .collect(),
+            None => return 0,
+        };
+        let with_id = sessions
+            .iter()
+            .any(|session| session.boop_ids)
+            .then(with_id);
+
+        sessions
+            .into_iter()
+            .filter(|session| {
+                let msg = match &with_id {
+                    Some(with_id) if session.boop_ids => with_id,
+                    _ => plain,
+                };
+                session.channel.send(msg.clone()).is_ok()
+            })
+            .count()
+    }
+
+    /// Sends relayed boops to a connection with their IDs. Returns `false` if the connection
+    /// doesn't exist.
+    pub fn enable_boop_ids(&self, client_key: &str, connection_id: &str) -> bool {
+        let mut shard = self.shard(client_key).write().unwrap();
+
+        match shard
+            .get_mut(client_key)
+            .and_then(|inner_map| inner_map.get_mut(connection_id))
+        {
+            Some(session) => {
+                session.boop_ids = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the status of a connection. Returns `false` if it doesn't exist.
+    pub fn set_status(&self, client_key: &str, connection_id: &str, status: Status) -> bool {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let mut shard = self.shard(client_key).write().unwrap();
+
+        match shard
+            .get_mut(client_key)
+            .and_then(|inner_map| inner_map.get_mut(connection_id))
+        {
+            Some(session) => {
+                session.status = Status { seq, ..status };
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Status of the key's most available connection (the most recently set one if several
+    /// are equally available), `None` if the key is offline.
+    pub fn presence(&self, client_key: &str) -> Option<Status> {
+        let shard = self.shard(client_key).read().unwrap();
+
+        shard.get(client_key).and_then(|inner_map| {
+            inner_map
+                .values()
+                .map(|session| &session.status)
+                .min_by_key(|status| (status.state, std::cmp::Reverse(status.seq)))
+                .cloned()
+        })
+    }
+
+    /// Sorted device names of the key's connections, `None` if the key is offline.
+    /// Connections without a device name are not listed.
+    pub fn devices(&self, client_key: &str) -> Option<Vec<String>> {
+        let shard = self.shard(client_key).read().unwrap();
+
+        shard.get(client_key).map(|inner_map| {
+            let mut devices: Vec<String> = inner_map
+                .values()
+                .filter_map(|session| session.device.clone())
+                .collect();
+            devices.sort();
+            devices.dedup();
+            devices
+        })
+    }
+
+    /// Sends the message to every connection. Returns the number of connections that
+    /// accepted the message.
+    pub fn broadcast(&self, msg: &MessageType) -> usize {
+        let mut sent = 0;
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            sent += shard
+                .values()
+                .flat_map(HashMap::values)
+                .filter(|session| session.channel.send(msg.clone()).is_ok())
+                .count();
+        }
+
+        sent
+    }
+
+    /// Key, connection ID and device name of every connection.
+    pub fn connections(&self) -> Vec<(String, String, Option<String>)> {
+        let mut connections = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            for (client_key, inner_map) in shard.iter() {
+                for (connection_id, session) in inner_map.iter() {
+                    connections.push((
+                        client_key.clone(),
+                        connection_id.clone(),
+                        session.device.clone(),
+                    ));
+                }
+            }
+        }
+
+        connections
+    }
+
+    /// Number of online keys and number of connections.
+    pub fn counts(&self) -> (usize, usize) {
+        let mut keys = 0;
+        let mut connections = 0;
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            keys += shard.len();
+            connections += shard.values().map(HashMap::len).sum::<usize>();
+        }
+
+        (keys, connections)
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::{Registry, SessionLimitReached, Status};
+    use crate::{
+        message::{MessageType, PresenceState},
+        outbox::{self, QueueConfig},
+        stats::Stats,
+    };
+
+    fn boop(source: &str) -> MessageType {
+        MessageType::BOOP(String::from(source), None)
+    }
+
+    fn channel() -> (outbox::Sender, outbox::Receiver) {
+        outbox::channel(&QueueConfig::default(), Arc::new(Stats::new()))
+    }
+
+    #[tokio::test]
+    async fn test_registry_fan_out() {
+        let registry = Registry::new();
+        let (phone, mut phone_rx) = channel();
+        let (laptop, mut laptop_rx) = channel();
+        let (other, mut other_rx) = channel();
+
+        registry.register("foo", "phone", None, phone);
+        registry.register("foo", "laptop", None, laptop);
+        registry.register("bar", "other", None, other);
+
+        assert!(registry.presence("foo").is_some());
+        assert!(registry.presence("baz").is_none());
+        assert_eq!(registry.counts(), (2, 3));
+
+        assert_eq!(registry.fan_out("foo", &boop("bar")), 2);
+        assert_eq!(registry.fan_out("baz", &boop("bar")), 0);
+        assert_eq!(phone_rx.recv().await, Some(boop("bar")));
+        assert_eq!(laptop_rx.recv().await, Some(boop("bar")));
+
+        assert_eq!(registry.broadcast(&MessageType::PING), 3);
+        assert_eq!(other_rx.recv().await, Some(MessageType::PING));
+    }
+
+    #[tokio::test]
+    async fn test_registry_boop_ids() {
+        let registry = Registry::new();
+        let (phone, mut phone_rx) = channel();
+        let (laptop, mut laptop_rx) = channel();
+
+        registry.register("foo", "phone", None, phone);
+        registry.register("foo", "laptop", None, laptop);
+        assert!(registry.enable_boop_ids("foo", "phone"));
+        assert!(!registry.enable_boop_ids("foo", "tablet"));
+
+        let with_id = || MessageType::BOOP(String::from("bar"), Some(7));
+        assert_eq!(registry.fan_out_boop("foo", None, &boop("bar"), with_id), 2);
+        assert_eq!(phone_rx.recv().await, Some(with_id()));
+        assert_eq!(laptop_rx.recv().await, Some(boop("bar")));
+
+        // no ID is made up for offline keys
+        assert_eq!(
+            registry.fan_out_boop("baz", None, &boop("bar"), || unreachable!()),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registry_unregister() {
+        let registry = Registry::new();
+        let (phone, mut phone_rx) = channel();
+        let (laptop, mut laptop_rx) = channel();
+        let (other, mut other_rx) = channel();
+
+        registry.register("foo", "phone", None, phone);
+        registry.register("foo", "laptop", None, laptop);
+        registry.register("bar", "other", None, other);
+
+        assert!(registry.unregister("foo", "phone"));
+        assert!(!registry.unregister("foo", "phone"));
+        assert!(registry.presence("foo").is_some());
+        assert_eq!(phone_rx.recv().await, None);
+
+        assert!(registry.kick_connection("laptop"));
+        assert!(!registry.kick_connection("laptop"));
+        assert!(registry.presence("foo").is_none());
+        assert_eq!(laptop_rx.recv().await, None);
+
+        assert_eq!(registry.kick_key("bar"), 1);
+        assert_eq!(registry.kick_key("bar"), 0);
+        assert_eq!(other_rx.recv().await, None);
+
+        assert_eq!(registry.counts(), (0, 0));
+        assert!(registry.connections().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registry_devices() {
+        let registry = Registry::new();
+        let (phone, mut phone_rx) = channel();
+        let (laptop, mut laptop_rx) = channel();
+        let (unnamed, _unnamed_rx) = channel();
+
+        registry.register("foo", "1", Some("phone"), phone);
+        registry.register("foo", "2", Some("laptop"), laptop);
+        registry.register("foo", "3", None, unnamed);
+
+        assert_eq!(
+            registry.devices("foo"),
+            Some(vec![String::from("laptop"), String::from("phone")])
+        );
+        assert_eq!(registry.devices("bar"), None);
+
+        assert_eq!(
+            registry.fan_out_boop("foo", Some("phone"), &boop("bar"), || unreachable!()),
+            1
+        );
+        assert_eq!(
+            registry.fan_out_boop("foo", Some("tablet"), &boop("bar"), || unreachable!()),
+            0
+        );
+        assert_eq!(phone_rx.recv().await, Some(boop("bar")));
+
+        registry.unregister("foo", "1");
+        registry.unregister("foo", "2");
+        assert_eq!(registry.devices("foo"), Some(Vec::new()));
+        assert_eq!(laptop_rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_registry_presence() {
+        let registry = Registry::new();
+        registry.register("foo", "phone", None, channel().0);
+        registry.register("foo", "laptop", None, channel().0);
+
+        assert_eq!(registry.presence("bar"), None);
+        assert_eq!(
+            registry.presence("foo").unwrap().state,
+            PresenceState::Available
+        );
+
+        let busy = Status::new(PresenceState::Busy, Some(String::from("in a meeting")));
+        assert!(registry.set_status("foo", "phone", busy.clone()));
+        assert!(registry.set_status("foo", "laptop", Status::new(PresenceState::Away, None)));
+        assert!(!registry.set_status("foo", "tablet", busy.clone()));
+
+        // the most available device wins
+        let presence = registry.presence("foo").unwrap();
+        assert_eq!((presence.state, presence.text), (busy.state, busy.text));
+
+        // the latest of equally available devices wins
+        let busy = Status::new(PresenceState::Busy, Some(String::from("on a call")));
+        assert!(registry.set_status("foo", "laptop", busy.clone()));
+        assert_eq!(registry.presence("foo").unwrap().text, busy.text);
+
+        registry.unregister("foo", "laptop");
+        assert_eq!(
+            registry.presence("foo").unwrap().text,
+            Some(String::from("in a meeting"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registry_session_limit() {
+        let registry = Registry::new();
+        let (phone, _phone_rx) = channel();
+        let (laptop, _laptop_rx) = channel();
+        let (tablet, _tablet_rx) = channel();
+
+        assert!(registry
+            .register_limited("foo", "phone", None, phone, 2, false)
+            .unwrap()
+            .is_empty());
+        registry.register("foo", "laptop", None, laptop);
+        assert!(matches!(
+            registry.register_limited("foo", "tablet", None, tablet, 2, false),
+            Err(SessionLimitReached)
+        ));
+        assert_eq!(registry.counts(), (1, 2));
+
+        let (tablet, _tablet_rx) = channel();
+        let evicted = registry
+            .register_limited("foo", "tablet", None, tablet, 2, true)
+            .unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0, "phone");
+        assert_eq!(registry.counts(), (1, 2));
+
+        let (other, _other_rx) = channel();
+        assert!(matches!(
+            registry.register_limited("bar", "other", None, other, 0, true),
+            Err(SessionLimitReached)
+        ));
+        assert!(registry.presence("bar").is_none());
+    }
+
+    /// Boop throughput with thousands of concurrent connections, compared to a single lock.
+    /// The difference only shows on machines with several cores.
+    /// Run with `cargo test --release -- --ignored --nocapture bench_registry`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_registry_throughput() {
+        const CONNECTIONS: usize = 5_000;
+        const BOOPERS: usize = 64;
+        const BOOPS_PER_BOOPER: usize = 20_000;
+
+        for shards in [1, 64] {
+            let registry = Arc::new(Registry::with_shards(shards));
+
+            let mut receivers = Vec::new();
+            for i in 0..CONNECTIONS {
+                let (tx, mut rx) = channel();
+                registry.register(&format!("key{}", i), &format!("connection{}", i), None, tx);
+                receivers.push(tokio::spawn(async move {
+                    let mut received = 0;
+                    while rx.recv().await.is_some() {
+                        received += 1;
+                    }
+                    received
+                }));
+            }
+
+            let start = Instant::now();
+            let boopers: Vec<_> = (0..BOOPERS)
+                .map(|booper| {
+                    let registry = Arc::clone(&registry);
+                    tokio::spawn(async move {
+                        let source = format!("key{}", booper);
+                        for i in 0..BOOPS_PER_BOOPER {
+                            let target = format!("key{}", (booper * 7919 + i * 31) % CONNECTIONS);
+                            if registry.presence(&target).is_some() {
+                                registry.fan_out(&target, &boop(&source));
+                            }
+                            // devices logging in and out in the meantime
+                            if i % 16 == 0 {
+                                let connection_id = format!("churn{}-{}", booper, i);
+                                registry.register(&target, &connection_id, None, channel().0);
+                                registry.unregister(&target, &connection_id);
+                            }
+                            if i % 64 == 0 {
+                                tokio::task::yield_now().await;
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for booper in boopers {
+                booper.await.unwrap();
+            }
+            let elapsed = start.elapsed();
+
+            for i in 0..CONNECTIONS {
+                registry.kick_key(&format!("key{}", i));
+            }
+            let mut received = 0;
+            for receiver in receivers {
+                received += receiver.await.unwrap();
+            }
+
+            let boops = BOOPERS * BOOPS_PER_BOOPER;
+            println!(
+                "{:>2} shard(s): {} boops to {} connections in {:?} ({:.0} boops/s, {} delivered)",
+                shards,
+                boops,
+                CONNECTIONS,
+                elapsed,
+                boops as f64 / elapsed.max(Duration::from_nanos(1)).as_secs_f64(),
+                received
+            );
+        }
+    }
+}
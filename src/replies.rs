@@ -0,0 +1,91 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+/// Relayed boops that can be answered with `BOOPBACK <id>`.
+pub struct RecentBoops {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    next_id: u64,
+    // ordered by ID, the oldest first
+    boops: VecDeque<RecentBoop>,
+}
+
+struct RecentBoop {
+    id: u64,
+    from: String,
+    to: String,
+}
+
+impl RecentBoops {
+    /// Remembers the last `capacity` boops. IDs start at `first_id`, e.g. derived from the
+    /// current time so IDs from before a restart aren't handed out again.
+    pub fn new(capacity: usize, first_id: u64) -> RecentBoops {
+        RecentBoops {
+            capacity,
+            inner: Mutex::new(Inner {
+                next_id: first_id,
+                boops: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Assigns an ID to a boop from `from` to the key `to`, forgetting the oldest boop if
+    /// there are too many.
+    pub fn add(&self, from: &str, to: &str) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        if inner.boops.len() >= self.capacity {
+            inner.boops.pop_front();
+        }
+        inner.boops.push_back(RecentBoop {
+            id,
+            from: String::from(from),
+            to: String::from(to),
+        });
+
+        id
+    }
+
+    /// Sender of the boop with the ID, if it was sent to `to` and is still remembered.
+    pub fn sender(&self, id: u64, to: &str) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        let index = inner.boops.binary_search_by_key(&id, |boop| boop.id).ok()?;
+
+        let boop = &inner.boops[index];
+        (boop.to == to).then(|| boop.from.clone())
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::RecentBoops;
+
+    #[test]
+    fn test_recent_boops() {
+        let boops = RecentBoops::new(2, 100);
+        assert_eq!(boops.add("foo", "bar"), 100);
+        assert_eq!(boops.add("bar", "foo"), 101);
+
+        assert_eq!(boops.sender(100, "bar"), Some(String::from("foo")));
+        assert_eq!(boops.sender(101, "foo"), Some(String::from("bar")));
+        // only the recipient can answer
+        assert_eq!(boops.sender(100, "foo"), None);
+        assert_eq!(boops.sender(102, "bar"), None);
+
+        // the oldest boop is forgotten
+        assert_eq!(boops.add("foo", "baz"), 102);
+        assert_eq!(boops.sender(100, "bar"), None);
+        assert_eq!(boops.sender(101, "foo"), Some(String::from("bar")));
+        assert_eq!(boops.sender(102, "baz"), Some(String::from("foo")));
+    }
+}
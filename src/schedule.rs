@@ -0,0 +1,255 @@
+use std::{
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+
+/// Limits of the boops scheduled with `BOOP <key> AT <time>` and `BOOP <key> IN <seconds>`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Pending boops per sender.
+    pub max_pending: usize,
+    /// How far ahead boops can be scheduled.
+    pub max_delay_days: u64,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> ScheduleConfig {
+        ScheduleConfig {
+            max_pending: 50,
+            max_delay_days: 365,
+        }
+    }
+}
+
+impl ScheduleConfig {
+    pub fn max_delay(&self) -> u64 {
+        self.max_delay_days.saturating_mul(24 * 60 * 60)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduledBoop {
+    pub id: u64,
+    pub from: String,
+    pub to: String, // `<key>` or `<key>/<device>`
+    pub time: u64,  // unix timestamp in seconds
+}
+
+/// Boops waiting for their delivery time, backed by the scheduled boops file.
+pub struct ScheduleStore {
+    path: PathBuf,
+    // held while writing so the file is written in order
+    scheduled: Mutex<Scheduled>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Scheduled {
+    next_id: u64,
+    boops: Vec<ScheduledBoop>,
+}
+
+impl ScheduleStore {
+    /// Reads the scheduled boops file, a missing file is an empty store.
+    pub async fn open(path: &Path) -> Result<ScheduleStore, Error> {
+        let scheduled = if path.exists() {
+            let contents = fs::read_to_string(path).await?;
+            serde_json::from_str(contents.as_str())?
+        } else {
+            Scheduled::default()
+        };
+
+        Ok(ScheduleStore {
+            path: path.to_path_buf(),
+            scheduled: Mutex::new(scheduled),
+        })
+    }
+
+    /// Schedules a boop and writes the updated file. Returns `None` if the sender already
+    /// has `max_pending` boops scheduled.
+    pub async fn add(
+        &self,
+        from: &str,
+        to: &str,
+        time: u64,
+        max_pending: usize,
+    ) -> Result<Option<ScheduledBoop>, Error> {
+        self.update(|scheduled| {
+            let pending = scheduled
+                .boops
+                .iter()
+                .filter(|boop| boop.from == from)
+                .count();
+            if pending >= max_pending {
+                return None;
+            }
+
+            let boop = ScheduledBoop {
+                id: scheduled.next_id,
+                from: String::from(from),
+                to: String::from(to),
+                time,
+            };
+            scheduled.next_id += 1;
+            scheduled.boops.push(boop.clone());
+
+            Some(boop)
+        })
+        .await
+    }
+
+    /// The pending boops of the sender, the next one first.
+    pub async fn pending(&self, from: &str) -> Vec<ScheduledBoop> {
+        let mut boops: Vec<ScheduledBoop> = self
+            .scheduled
+            .lock()
+            .await
+            .boops
+            .iter()
+            .filter(|boop| boop.from == from)
+            .cloned()
+            .collect();
+        boops.sort_by_key(|boop| (boop.time, boop.id));
+
+        boops
+    }
+
+    /// Removes a pending boop of the sender. Returns `false` if there is none with the ID.
+    pub async fn cancel(&self, from: &str, id: u64) -> Result<bool, Error> {
+        self.update(|scheduled| {
+            let before = scheduled.boops.len();
+            scheduled
+                .boops
+                .retain(|boop| !(boop.id == id && boop.from == from));
+
+            scheduled.boops.len() < before
+        })
+        .await
+    }
+
+    /// Removes all pending boops of the sender, e.g. when it gets banned. Returns how many.
+    pub async fn cancel_all(&self, from: &str) -> Result<usize, Error> {
+        self.update(|scheduled| {
+            let before = scheduled.boops.len();
+            scheduled.boops.retain(|boop| boop.from != from);
+
+            before - scheduled.boops.len()
+        })
+        .await
+    }
+
+    /// Removes and returns the boops due at `now`, the oldest first.
+    pub async fn take_due(&self, now: u64) -> Result<Vec<ScheduledBoop>, Error> {
+        // checked every second, only write the file when something is due
+        let scheduled = self.scheduled.lock().await;
+        if !scheduled.boops.iter().any(|boop| boop.time <= now) {
+            return Ok(Vec::new());
+        }
+        drop(scheduled);
+
+        self.update(|scheduled| {
+            let (mut due, pending): (Vec<ScheduledBoop>, Vec<ScheduledBoop>) =
+                scheduled.boops.drain(..).partition(|boop| boop.time <= now);
+            scheduled.boops = pending;
+            due.sort_by_key(|boop| (boop.time, boop.id));
+
+            due
+        })
+        .await
+    }
+
+    /// Changes the scheduled boops and writes them to the file, or restores them if that
+    /// fails.
+    async fn update<T>(&self, change: impl FnOnce(&mut Scheduled) -> T) -> Result<T, Error> {
+        let mut scheduled = self.scheduled.lock().await;
+        let previous = scheduled.clone();
+        let result = change(&mut scheduled);
+
+        if let Err(err) = write_schedule_file(&self.path, &scheduled).await {
+            *scheduled = previous;
+            return Err(err);
+        }
+
+        Ok(result)
+    }
+}
+
+async fn write_schedule_file(path: &Path, scheduled: &Scheduled) -> Result<(), Error> {
+    let contents = serde_json::to_string_pretty(scheduled)?;
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::{ScheduleStore, ScheduledBoop};
+
+    fn boop(id: u64, from: &str, to: &str, time: u64) -> ScheduledBoop {
+        ScheduledBoop {
+            id,
+            from: String::from(from),
+            to: String::from(to),
+            time,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_schedule_store() {
+        let path =
+            std::env::temp_dir().join(format!("boop-scheduled-{}.json", uuid::Uuid::new_v4()));
+
+        let store = ScheduleStore::open(&path).await.unwrap();
+        assert_eq!(
+            store.add("foo", "bar", 300, 2).await.unwrap(),
+            Some(boop(0, "foo", "bar", 300))
+        );
+        store.add("foo", "baz/phone", 200, 2).await.unwrap();
+        store.add("bar", "foo", 100, 2).await.unwrap();
+        // over max_pending
+        assert_eq!(store.add("foo", "bar", 400, 2).await.unwrap(), None);
+
+        // pending boops and IDs survive a restart
+        let store = ScheduleStore::open(&path).await.unwrap();
+        assert_eq!(
+            store.pending("foo").await,
+            vec![boop(1, "foo", "baz/phone", 200), boop(0, "foo", "bar", 300)]
+        );
+        assert_eq!(
+            store.add("bar", "foo", 500, 2).await.unwrap(),
+            Some(boop(3, "bar", "foo", 500))
+        );
+
+        // only the sender can cancel its boops
+        assert!(!store.cancel("bar", 1).await.unwrap());
+        assert!(store.cancel("foo", 1).await.unwrap());
+        assert!(!store.cancel("foo", 1).await.unwrap());
+        store.add("baz", "foo", 600, 2).await.unwrap();
+        store.add("baz", "bar", 700, 2).await.unwrap();
+        assert_eq!(store.cancel_all("baz").await.unwrap(), 2);
+        assert!(store.pending("baz").await.is_empty());
+
+        assert_eq!(
+            store.take_due(300).await.unwrap(),
+            vec![boop(2, "bar", "foo", 100), boop(0, "foo", "bar", 300)]
+        );
+        assert!(store.take_due(300).await.unwrap().is_empty());
+
+        let store = ScheduleStore::open(&path).await.unwrap();
+        assert!(store.pending("foo").await.is_empty());
+        assert_eq!(store.pending("bar").await, vec![boop(3, "bar", "foo", 500)]);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,1448 @@
+use chrono::Utc;
+use rustls_pemfile::{certs, pkcs8_private_keys};
+use std::{
+    fs::File,
+    future::{self, Future},
+    io::{self, Error},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{
+        split, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf,
+        WriteHalf,
+    },
+    sync::{mpsc, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_rustls::{
+    rustls::{self, Certificate, PrivateKey},
+    TlsAcceptor,
+};
+
+use crate::{
+    clients::{key_is_valid, ClientStore},
+    config::RelayConfig,
+    connlimit::{ConnectionPermit, ConnectionTracker, Refusal},
+    dnd::HeldBoops,
+    history::{BoopKind, HistoryEntry, HistoryStore},
+    invites::InviteStore,
+    lastseen::LastSeenStore,
+    listener::{self, Accepted, ListenAddr, Listener, Peer},
+    message::{
+        create_message_text, parse_message, DndCommand, LoginOptions, MessageErrorKind, MessageType,
+    },
+    motd::Motd,
+    outbox,
+    pairstats::PairStatsStore,
+    proxy,
+    ratelimit::{CommandKind, RateLimiter, Verdict},
+    registry::{Registry, Status},
+    replies::RecentBoops,
+    schedule::ScheduleStore,
+    settings::SettingsStore,
+    stats::Stats,
+    unix_time,
+    webhooks::{WebhookEvent, Webhooks},
+    Rx, Tx,
+};
+
+/// Long-lived services used by the connection handlers and the admin interface.
+pub(crate) struct Relay {
+    pub(crate) registry: Registry,
+    pub(crate) clients: ClientStore,
+    pub(crate) invites: InviteStore,
+    pub(crate) last_seen: LastSeenStore,
+    pub(crate) history: Option<HistoryStore>,
+    pub(crate) pair_stats: PairStatsStore,
+    pub(crate) scheduled: ScheduleStore,
+    pub(crate) settings: SettingsStore,
+    pub(crate) motd: Motd,
+    pub(crate) config: RelayConfig,
+    pub(crate) limiter: RateLimiter,
+    pub(crate) connections: ConnectionTracker,
+    pub(crate) held_boops: HeldBoops,
+    pub(crate) recent_boops: RecentBoops,
+    pub(crate) webhooks: Webhooks,
+    pub(crate) stats: Arc<Stats>,
+}
+
+/// How often boops held during quiet hours are checked for delivery.
+const HELD_BOOPS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often scheduled boops are checked for delivery.
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often changed boop counters are written to the pair stats file.
+const PAIR_STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often boops past the retention period are dropped from the history.
+const HISTORY_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Relayed boops that can be answered with a boop-back, the oldest are forgotten first.
+const RECENT_BOOPS: usize = 10_000;
+
+/// How long refused connections get for the TLS handshake before the refusal is sent.
+const REFUSAL_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a shutdown waits for the closed connections to finish their handlers.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Stand-in for deadlines too far away to represent, about 30 years like tokio's own.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+enum TlsSource {
+    Files { cert: PathBuf, key: PathBuf },
+    Config(Arc<rustls::ServerConfig>),
+}
+
+enum ListenerConfig {
+    Tls(String),
+    Plain(String),
+    Unix(PathBuf),
+}
+
+/// Configures and starts a relay server.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// let server = boop_relay::ServerBuilder::new("clients.json")
+///     .listen("0.0.0.0:6969")
+///     .tls_files("cert.pem", "key.pem")
+///     .start()
+///     .await?;
+/// server.wait().await
+/// # }
+/// ```
+pub struct ServerBuilder {
+    clients_config: PathBuf,
+    listeners: Vec<ListenerConfig>,
+    public_plain: bool,
+    tls: Option<TlsSource>,
+    invites: Option<PathBuf>,
+    last_seen: Option<PathBuf>,
+    history: Option<PathBuf>,
+    pair_stats: Option<PathBuf>,
+    scheduled: Option<PathBuf>,
+    settings: Option<PathBuf>,
+    motd: Option<PathBuf>,
+    admin_socket: Option<PathBuf>,
+    config: RelayConfig,
+}
+
+impl ServerBuilder {
+    pub fn new(clients_config: impl Into<PathBuf>) -> ServerBuilder {
+        ServerBuilder {
+            clients_config: clients_config.into(),
+            listeners: Vec::new(),
+            public_plain: false,
+            tls: None,
+            invites: None,
+            last_seen: None,
+            history: None,
+            pair_stats: None,
+            scheduled: None,
+            settings: None,
+            motd: None,
+            admin_socket: None,
+            config: RelayConfig::default(),
+        }
+    }
+
+    /// Accepts TLS connections on the address, requires a certificate. Can be called several
+    /// times; host names listen on every address they resolve to.
+    pub fn listen(mut self, addr: impl Into<String>) -> ServerBuilder {
+        self.listeners.push(ListenerConfig::Tls(addr.into()));
+        self
+    }
+
+    /// Accepts plaintext TCP connections on the address, e.g. for local development or
+    /// behind a TLS-terminating proxy. Only loopback addresses are allowed, unless
+    /// [`allow_public_plain`](ServerBuilder::allow_public_plain) is set.
+    pub fn listen_plain(mut self, addr: impl Into<String>) -> ServerBuilder {
+        self.listeners.push(ListenerConfig::Plain(addr.into()));
+        self
+    }
+
+    /// Accepts plaintext connections on a unix socket (unix only).
+    pub fn listen_unix(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.listeners.push(ListenerConfig::Unix(path.into()));
+        self
+    }
+
+    /// Allows plaintext TCP listeners on non-loopback addresses.
+    pub fn allow_public_plain(mut self, allow: bool) -> ServerBuilder {
+        self.public_plain = allow;
+        self
+    }
+
+    /// PEM encoded certificate chain and PKCS8 private key.
+    pub fn tls_files(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> ServerBuilder {
+        self.tls = Some(TlsSource::Files {
+            cert: cert.into(),
+            key: key.into(),
+        });
+        self
+    }
+
+    /// Ready-made TLS config, e.g. with an in-memory certificate.
+    pub fn tls_config(mut self, config: Arc<rustls::ServerConfig>) -> ServerBuilder {
+        self.tls = Some(TlsSource::Config(config));
+        self
+    }
+
+    /// Invite codes file (default: invites.json next to the client config file).
+    pub fn invites(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.invites = Some(path.into());
+        self
+    }
+
+    /// Last-seen times file (default: last_seen.json next to the client config file).
+    pub fn last_seen(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.last_seen = Some(path.into());
+        self
+    }
+
+    /// Boop history database, enables the `HISTORY` command (disabled if not set).
+    pub fn history(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.history = Some(path.into());
+        self
+    }
+
+    /// Boop counters per pair file (default: pair_stats.json next to the client config file).
+    pub fn pair_stats(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.pair_stats = Some(path.into());
+        self
+    }
+
+    /// Scheduled boops file (default: scheduled.json next to the client config file).
+    pub fn scheduled(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.scheduled = Some(path.into());
+        self
+    }
+
+    /// Per-user settings file for blocks, DND and last-seen sharing (default: settings.json
+    /// next to the client config file).
+    pub fn settings(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.settings = Some(path.into());
+        self
+    }
+
+    /// Message of the day file, sent to clients after login.
+    pub fn motd(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.motd = Some(path.into());
+        self
+    }
+
+    /// Unix socket for the admin interface (disabled if not set).
+    pub fn admin_socket(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.admin_socket = Some(path.into());
+        self
+    }
+
+    pub fn config(mut self, config: RelayConfig) -> ServerBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Reads the config files, binds the listeners and starts accepting connections.
+    pub async fn start(self) -> io::Result<ServerHandle> {
+        if self.listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no listen address configured",
+            ));
+        }
+
+        let clients = ClientStore::open(&self.clients_config).await?;
+        info!("{} client entries read", clients.len().await);
+        let motd = Motd::load(self.motd.as_deref()).await?;
+        let invites_path = self
+            .invites
+            .unwrap_or_else(|| self.clients_config.with_file_name("invites.json"));
+        let last_seen_path = self
+            .last_seen
+            .unwrap_or_else(|| self.clients_config.with_file_name("last_seen.json"));
+        let last_seen = LastSeenStore::open(&last_seen_path).await?;
+        let history = match &self.history {
+            Some(path) => Some(HistoryStore::open(path, self.config.history.clone()).await?),
+            None => None,
+        };
+        let pair_stats_path = self
+            .pair_stats
+            .unwrap_or_else(|| self.clients_config.with_file_name("pair_stats.json"));
+        let pair_stats = PairStatsStore::open(&pair_stats_path).await?;
+        let scheduled_path = self
+            .scheduled
+            .unwrap_or_else(|| self.clients_config.with_file_name("scheduled.json"));
+        let scheduled = ScheduleStore::open(&scheduled_path).await?;
+        let settings_path = self
+            .settings
+            .unwrap_or_else(|| self.clients_config.with_file_name("settings.json"));
+        let settings = SettingsStore::open(&settings_path).await?;
+
+        let uses_tls = self
+            .listeners
+            .iter()
+            .any(|listener| matches!(listener, ListenerConfig::Tls(_)));
+        let acceptor = match self.tls {
+            _ if !uses_tls => None,
+            Some(TlsSource::Files { cert, key }) => {
+                Some(TlsAcceptor::from(load_tls_config(&cert, &key)?))
+            }
+            Some(TlsSource::Config(config)) => Some(TlsAcceptor::from(config)),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no TLS certificate configured",
+                ))
+            }
+        };
+
+        let mut listeners = Vec::new();
+        for listener in &self.listeners {
+            match listener {
+                ListenerConfig::Tls(addr) => {
+                    for addr in resolve(addr)? {
+                        listeners.push(Listener::Tcp {
+                            listener: listener::bind_tcp(addr)?,
+                            tls: acceptor.clone(),
+                        });
+                    }
+                }
+                ListenerConfig::Plain(addr) => {
+                    for addr in resolve(addr)? {
+                        if !addr.ip().is_loopback() && !self.public_plain {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!(
+                                    "refusing plaintext connections on non-loopback address {}",
+                                    addr
+                                ),
+                            ));
+                        }
+                        listeners.push(Listener::Tcp {
+                            listener: listener::bind_tcp(addr)?,
+                            tls: None,
+                        });
+                    }
+                }
+                ListenerConfig::Unix(path) => listeners.push(listener::bind_unix(path)?),
+            }
+        }
+
+        let mut local_addrs = Vec::new();
+        for listener in &listeners {
+            let addr = listener.local_addr()?;
+            info!("listening on {}", addr);
+            local_addrs.push(addr);
+        }
+
+        let stats = Arc::new(Stats::new());
+        let webhooks = Webhooks::start(&self.config.webhooks, Arc::clone(&stats))?;
+        let relay = Arc::new(Relay {
+            registry: Registry::new(),
+            clients,
+            invites: InviteStore::new(&invites_path),
+            last_seen,
+            history,
+            pair_stats,
+            scheduled,
+            settings,
+            motd,
+            limiter: RateLimiter::new(self.config.rate_limits.clone()),
+            connections: ConnectionTracker::new(self.config.connection_limits.clone()),
+            held_boops: HeldBoops::new(self.config.dnd.clone()),
+            config: self.config,
+            // IDs from before a restart stay unused unless there were ~65k boops per second
+            recent_boops: RecentBoops::new(RECENT_BOOPS, unix_time() << 16),
+            webhooks,
+            stats,
+        });
+
+        let admin = match &self.admin_socket {
+            Some(path) => Some((path.clone(), start_admin_socket(path, &relay)?)),
+            None => None,
+        };
+
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let task = tokio::spawn(serve(listeners, Arc::clone(&relay), shutdown_rx));
+
+        Ok(ServerHandle {
+            local_addrs,
+            relay,
+            shutdown,
+            task,
+            admin,
+        })
+    }
+}
+
+/// All addresses a listen address resolves to, e.g. IPv4 and IPv6 for `localhost:6969`.
+fn resolve(addr: &str) -> io::Result<Vec<SocketAddr>> {
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for addr in addr.to_socket_addrs()? {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    if addrs.is_empty() {
+        return Err(io::Error::from(io::ErrorKind::AddrNotAvailable));
+    }
+    Ok(addrs)
+}
+
+/// A running relay server.
+///
+/// Dropping the handle leaves the server running in the background.
+pub struct ServerHandle {
+    local_addrs: Vec<ListenAddr>,
+    relay: Arc<Relay>,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<io::Result<()>>,
+    admin: Option<(PathBuf, JoinHandle<()>)>,
+}
+
+impl ServerHandle {
+    /// Addresses the server is listening on, useful when binding to port 0.
+    pub fn local_addrs(&self) -> &[ListenAddr] {
+        &self.local_addrs
+    }
+
+    /// Address of the first TCP listener (TLS or plaintext).
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs.iter().find_map(|addr| match addr {
+            ListenAddr::Tls(addr) | ListenAddr::Plain(addr) => Some(*addr),
+            ListenAddr::Unix(_) => None,
+        })
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.relay.stats
+    }
+
+    pub fn clients(&self) -> &ClientStore {
+        &self.relay.clients
+    }
+
+    pub fn settings(&self) -> &SettingsStore {
+        &self.relay.settings
+    }
+
+    /// Runs until a listener fails.
+    pub async fn wait(self) -> io::Result<()> {
+        self.run_until(future::pending()).await
+    }
+
+    /// Stops accepting connections and closes all logged in connections.
+    pub async fn shutdown(self) -> io::Result<()> {
+        self.run_until(future::ready(())).await
+    }
+
+    /// Runs until a listener fails or `signal` completes, then shuts the server down.
+    pub async fn run_until(mut self, signal: impl Future<Output = ()>) -> io::Result<()> {
+        let result = tokio::select! {
+            result = &mut self.task => result,
+            _ = signal => {
+                let _ = self.shutdown.send(true);
+                (&mut self.task).await
+            }
+        };
+
+        if let Some((path, admin)) = self.admin {
+            admin.abort();
+            let _ = std::fs::remove_file(path);
+        }
+        for addr in &self.local_addrs {
+            if let ListenAddr::Unix(path) = addr {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+
+        result.map_err(io::Error::other)?
+    }
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    certs(&mut std::io::BufReader::new(File::open(path)?))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))
+        .map(|mut certs| certs.drain(..).map(Certificate).collect())
+}
+
+fn load_keys(path: &Path) -> io::Result<Vec<PrivateKey>> {
+    pkcs8_private_keys(&mut std::io::BufReader::new(File::open(path)?))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid key"))
+        .map(|mut keys| keys.drain(..).map(PrivateKey).collect())
+}
+
+fn load_tls_config(cert: &Path, key: &Path) -> io::Result<Arc<rustls::ServerConfig>> {
+    let certs = load_certs(cert)?;
+    let mut keys = load_keys(key)?;
+    info!("{} TLS certs, {} TLS keys read", certs.len(), keys.len());
+    if keys.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no TLS key found",
+        ));
+    }
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, keys.remove(0))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    Ok(Arc::new(config))
+}
+
+/// Runs an accept loop per listener until shutdown or until one of them fails.
+async fn serve(
+    listeners: Vec<Listener>,
+    relay: Arc<Relay>,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let (stop, stop_rx) = watch::channel(false);
+    let (done, mut done_rx) = mpsc::unbounded_channel();
+    for listener in listeners {
+        let relay = Arc::clone(&relay);
+        let stop_rx = stop_rx.clone();
+        let done = done.clone();
+        tokio::spawn(async move {
+            let _ = done.send(accept_connections(listener, relay, stop_rx).await);
+        });
+    }
+    drop(done);
+    let held_boops = tokio::spawn(deliver_held_boops_periodically(Arc::clone(&relay)));
+    let scheduled = tokio::spawn(deliver_scheduled_boops_periodically(Arc::clone(&relay)));
+    let history = tokio::spawn(prune_history_periodically(Arc::clone(&relay)));
+    let pair_stats = tokio::spawn(flush_pair_stats_periodically(Arc::clone(&relay)));
+
+    let result = tokio::select! {
+        Some(Err(err)) = done_rx.recv() => Err(err),
+        Ok(()) = shutdown.changed() => Ok(()),
+        else => Ok(()),
+    };
+
+    // stop the remaining accept loops
+    let _ = stop.send(true);
+    while done_rx.recv().await.is_some() {}
+    held_boops.abort();
+    scheduled.abort();
+    history.abort();
+    pair_stats.abort();
+    relay.webhooks.stop();
+
+    // the connections are closed without going through their handlers
+    let mut online: Vec<String> = relay
+        .registry
+        .connections()
+        .into_iter()
+        .map(|(key, _, _)| key)
+        .collect();
+    online.sort();
+    online.dedup();
+    if let Err(err) = relay.last_seen.close(&online, unix_time()).await {
+        error!("failed to save the last-seen times: {}", err);
+    }
+
+    let closed = relay.registry.kick_all();
+    // let the handlers finish their last writes before the history and counters are closed
+    let deadline = Instant::now() + SHUTDOWN_GRACE_PERIOD;
+    while relay.connections.count() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    if let Some(history) = &relay.history {
+        history.close();
+    }
+    if let Err(err) = relay.pair_stats.close().await {
+        error!("failed to save the boop counters: {}", err);
+    }
+
+    info!("server shut down, {} connections closed", closed);
+    result
+}
+
+/// Delivers held boops of recipients whose quiet hours have ended.
+async fn deliver_held_boops_periodically(relay: Arc<Relay>) {
+    let mut interval = tokio::time::interval(HELD_BOOPS_INTERVAL);
+    loop {
+        interval.tick().await;
+        for recipient in relay.held_boops.recipients() {
+            deliver_held_boops(&relay, &recipient).await;
+        }
+    }
+}
+
+/// Sends the boops held for the key as a summary, one `BOOP` or `BOOPS` per sender, once
+/// it is online and out of do-not-disturb mode.
+async fn deliver_held_boops(relay: &Relay, key: &str) {
+    if relay.registry.presence(key).is_none() || relay.settings.dnd(key).await.is_active(Utc::now())
+    {
+        return;
+    }
+
+    for (sender, count) in relay.held_boops.take(key) {
+        // blocked after the boops were held
+        if relay.settings.blocks(key, &sender).await {
+            continue;
+        }
+
+        let delivered = match count {
+            1 => relay.registry.fan_out_boop(
+                key,
+                None,
+                &MessageType::BOOP(sender.clone(), None),
+                || MessageType::BOOP(sender.clone(), Some(relay.recent_boops.add(&sender, key))),
+            ),
+            _ => relay
+                .registry
+                .fan_out(key, &MessageType::BOOPS(sender.clone(), count)),
+        };
+        if delivered > 0 {
+            record_pair_stats(relay, &sender, key, u64::from(count)).await;
+            let entries = (0..count)
+                .map(|_| HistoryEntry {
+                    from: sender.clone(),
+                    to: String::from(key),
+                    timestamp: unix_time(),
+                    kind: BoopKind::Deferred,
+                })
+                .collect();
+            record_history(relay, entries).await;
+        }
+    }
+}
+
+/// Relays the boops whose delivery time has come like boops sent right now.
+async fn deliver_scheduled_boops_periodically(relay: Arc<Relay>) {
+    let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let due = match relay.scheduled.take_due(unix_time()).await {
+            Ok(due) => due,
+            Err(err) => {
+                error!("failed to save the scheduled boops: {}", err);
+                continue;
+            }
+        };
+
+        for boop in due {
+            // removed or banned after scheduling the boop
+            if !relay.clients.contains(&boop.from).await
+                || relay.clients.active_ban(&boop.from).await.is_some()
+            {
+                debug!("dropped scheduled boop {} of {}", boop.id, boop.from);
+                continue;
+            }
+
+            if let Some(answer) = relay_boop(&relay, &boop.from, &boop.to, None).await {
+                relay.registry.fan_out(&boop.from, &answer);
+            }
+        }
+    }
+}
+
+/// Relays a boop to `<key>`, or to a single device with `<key>/<device>`, or the boop-back
+/// to the boop with the `reply_to` ID. Boops to keys that blocked the sender are dropped like
+/// boops to offline keys, boops to keys in do-not-disturb mode are held (as plain boops).
+/// Returns the answer for the sender, if any.
+async fn relay_boop(
+    relay: &Relay,
+    from: &str,
+    partner: &str,
+    reply_to: Option<u64>,
+) -> Option<MessageType> {
+    let (partner_key, device) = match partner.split_once('/') {
+        Some((partner_key, device)) => (partner_key, Some(device)),
+        None => (partner, None),
+    };
+
+    let webhook_event = |delivered: bool| {
+        let (from, to, device) = (
+            String::from(from),
+            String::from(partner_key),
+            device.map(String::from),
+        );
+        if delivered {
+            WebhookEvent::BoopRelayed { from, to, device }
+        } else {
+            WebhookEvent::BoopUndeliverable { from, to, device }
+        }
+    };
+
+    if relay.settings.blocks(partner_key, from).await {
+        relay.webhooks.send(webhook_event(false));
+        return None;
+    }
+
+    let dnd = relay.settings.dnd(partner_key).await;
+    if dnd.is_active(Utc::now()) {
+        if !relay.held_boops.hold(partner_key, from) {
+            return Some(MessageType::ERROR(MessageErrorKind::RateLimited));
+        }
+        Stats::increment(&relay.stats.boops_held);
+        return dnd
+            .notify_senders
+            .then(|| MessageType::DEFERRED(String::from(partner_key)));
+    }
+
+    // connections without boop IDs get boop-backs as plain boops, IDs are only handed out
+    // for boops that reach a connection using them
+    let plain = MessageType::BOOP(String::from(from), None);
+    let delivered = relay
+        .registry
+        .fan_out_boop(partner_key, device, &plain, || match reply_to {
+            Some(id) => MessageType::BOOPBACK(Some(String::from(from)), id),
+            None => MessageType::BOOP(
+                String::from(from),
+                Some(relay.recent_boops.add(from, partner_key)),
+            ),
+        });
+    relay.webhooks.send(webhook_event(delivered > 0));
+    if delivered > 0 {
+        Stats::increment(&relay.stats.boops_relayed);
+        record_pair_stats(relay, from, partner_key, 1).await;
+        let entry = HistoryEntry {
+            from: String::from(from),
+            to: String::from(partner_key),
+            timestamp: unix_time(),
+            kind: match (reply_to, device) {
+                (Some(_), _) => BoopKind::BoopBack,
+                (None, Some(_)) => BoopKind::Device,
+                (None, None) => BoopKind::Boop,
+            },
+        };
+        record_history(relay, vec![entry]).await;
+    }
+
+    None
+}
+
+/// Drops boops past the retention period from the history, if it's enabled.
+async fn prune_history_periodically(relay: Arc<Relay>) {
+    let history = match &relay.history {
+        Some(history) => history,
+        None => return,
+    };
+
+    let mut interval = tokio::time::interval(HISTORY_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match history.prune(unix_time()).await {
+            Ok(0) => {}
+            Ok(count) => debug!("{} boops dropped from the history", count),
+            Err(err) => error!("failed to prune the boop history: {}", err),
+        }
+    }
+}
+
+/// Writes the boop counters that changed since the last run.
+async fn flush_pair_stats_periodically(relay: Arc<Relay>) {
+    let mut interval = tokio::time::interval(PAIR_STATS_FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = relay.pair_stats.flush().await {
+            error!("failed to save the boop counters: {}", err);
+        }
+    }
+}
+
+/// Counts relayed boops towards the streaks of the pair.
+async fn record_pair_stats(relay: &Relay, from: &str, to: &str, count: u64) {
+    relay.pair_stats.record(from, to, count, unix_time()).await;
+}
+
+/// Adds relayed boops to the history, if it's enabled.
+async fn record_history(relay: &Relay, entries: Vec<HistoryEntry>) {
+    if let Some(history) = &relay.history {
+        if let Err(err) = history.record(entries).await {
+            error!("failed to save boops to the history: {}", err);
+        }
+    }
+}
+
+async fn accept_connections(
+    listener: Listener,
+    relay: Arc<Relay>,
+    mut stop: watch::Receiver<bool>,
+) -> io::Result<()> {
+    loop {
+        let accepted = tokio::select! {
+            res = listener.accept() => res?,
+            Ok(()) = stop.changed() => return Ok(()),
+        };
+        let relay = Arc::clone(&relay);
+        Stats::increment(&relay.stats.connections_accepted);
+
+        tokio::spawn(async move {
+            let mut peer = accepted.peer();
+            // counted before any IO, so slow handshakes and PROXY headers are limited as well
+            let admission = relay
+                .connections
+                .admit(admission_ip(&accepted, &relay.config));
+            if let Err(err) = serve_connection(accepted, &mut peer, admission, &relay).await {
+                if err.kind() == io::ErrorKind::ConnectionReset {
+                    warn!("client {} forcefully closed the connection", peer);
+                } else {
+                    error!("connection error ({}) [{}]: {}", peer, err.kind(), err);
+                }
+            }
+        });
+    }
+}
+
+/// Address a new connection is counted under until its real address is known: the socket
+/// address, or none for trusted proxies, which are counted by the PROXY header address.
+fn admission_ip(accepted: &Accepted, config: &RelayConfig) -> Option<IpAddr> {
+    match accepted {
+        Accepted::Tls(_, peer_addr, _) | Accepted::Plain(_, peer_addr) => {
+            Some(peer_addr.ip()).filter(|ip| !config.proxy_protocol.is_trusted(*ip))
+        }
+        #[cfg(unix)]
+        Accepted::Unix(_) => None,
+    }
+}
+
+/// Resolves the real client address behind a proxy, does the TLS handshake if needed and
+/// handles the connection.
+async fn serve_connection(
+    accepted: Accepted,
+    peer: &mut Peer,
+    mut admission: Result<ConnectionPermit<'_>, Refusal>,
+    relay: &Relay,
+) -> io::Result<()> {
+    let proxy_config = &relay.config.proxy_protocol;
+
+    match accepted {
+        Accepted::Tls(mut stream, peer_addr, acceptor) => {
+            *peer = Peer::Tcp(proxy::real_peer_addr(&mut stream, peer_addr, proxy_config).await?);
+            rekey(&mut admission, peer);
+            debug!(
+                "received connection attempt from {}, trying tls handshake",
+                peer
+            );
+            let stream = if admission.is_ok() {
+                acceptor.accept(stream).await?
+            } else {
+                tokio::time::timeout(REFUSAL_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                    .await
+                    .map_err(|_| Error::new(io::ErrorKind::TimedOut, "tls handshake timed out"))??
+            };
+            handle_connection(stream, *peer, admission, relay).await
+        }
+        Accepted::Plain(mut stream, peer_addr) => {
+            *peer = Peer::Tcp(proxy::real_peer_addr(&mut stream, peer_addr, proxy_config).await?);
+            rekey(&mut admission, peer);
+            handle_connection(stream, *peer, admission, relay).await
+        }
+        #[cfg(unix)]
+        Accepted::Unix(stream) => handle_connection(stream, *peer, admission, relay).await,
+    }
+}
+
+/// Counts an admitted connection under its real address, refusing it if that address is
+/// over the limit.
+fn rekey(admission: &mut Result<ConnectionPermit<'_>, Refusal>, peer: &Peer) {
+    if let Ok(permit) = admission {
+        if let Err(refusal) = permit.rekey(peer.ip()) {
+            *admission = Err(refusal);
+        }
+    }
+}
+
+async fn handle_connection<S>(
+    stream: S,
+    peer: Peer,
+    admission: Result<ConnectionPermit<'_>, Refusal>,
+    relay: &Relay,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let clients = &relay.clients;
+    let stats = &relay.stats;
+
+    let (readhalf, mut writehalf) = split(stream);
+    let mut reader = BufReader::new(readhalf);
+
+    // counted until the connection is closed
+    let permit = match admission {
+        Ok(permit) => permit,
+        Err(refusal) => {
+            match refusal {
+                Refusal::Total => info!("connection refused, relay is full ({})", peer),
+                Refusal::PerIp => info!("connection refused, too many connections ({})", peer),
+            }
+            Stats::increment(&stats.refused_connections);
+            return send_error_and_close(writehalf, MessageErrorKind::TooManyConnections).await;
+        }
+    };
+
+    // check for connect call
+    let mut cmd_buffer = String::new();
+    let read_result = reader.read_line(&mut cmd_buffer).await;
+    if let Err(err) = read_result {
+        error!("there was an error reading from the connection: {}", &err);
+        return Err(err);
+    }
+
+    // Initial Handshake
+
+    let read = read_result.unwrap();
+    if read == 0 {
+        error!("EOF reached while reading from connection");
+        return Err(Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "EOF reached while reading from connection",
+        ));
+    }
+
+    let parser_res = parse_message(&cmd_buffer);
+    if let Err(err) = parser_res {
+        return send_error_and_close(writehalf, err.into()).await;
+    }
+
+    let (client_key, options) = match parser_res.unwrap() {
+        MessageType::CONNECT(key, password, options) => {
+            // CORRECT CONNECT CALL
+
+            let login_result = clients.login_is_valid(&key, &password).await;
+            if login_result.is_err() || !login_result.unwrap() {
+                // LOGIN WRONG
+                info!("login failed, key: {} ({})", &key, peer);
+                Stats::increment(&stats.failed_logins);
+                relay.webhooks.send(WebhookEvent::LoginFailed {
+                    key,
+                    addr: peer.to_string(),
+                });
+                return send_message_and_close(writehalf, MessageType::NO).await;
+            }
+
+            if let Some(ban) = clients.active_ban(&key).await {
+                // LOGIN CORRECT BUT ACCOUNT IS BANNED
+                info!("login refused, key is banned: {} ({})", &key, peer);
+                Stats::increment(&stats.failed_logins);
+                relay.webhooks.send(WebhookEvent::LoginFailed {
+                    key,
+                    addr: peer.to_string(),
+                });
+                return send_message_and_close(writehalf, MessageType::BANNED(ban.reason)).await;
+            }
+
+            // LOGIN CORRECT
+            info!("logged in: {} ({})", &key, peer);
+            Stats::increment(&stats.logins);
+            (key, options)
+        }
+        MessageType::REGISTER(invite, key, password) => {
+            if !key_is_valid(&key) {
+                return send_error_and_close(writehalf, MessageErrorKind::MalformedArguments).await;
+            }
+
+            if clients.contains(&key).await {
+                // KEY TAKEN
+                info!(
+                    "registration failed, key already exists: {} ({})",
+                    &key, peer
+                );
+                return send_error_and_close(writehalf, MessageErrorKind::NotAvailable).await;
+            }
+
+            if !relay.invites.reserve(&invite).await? {
+                // INVITE WRONG OR EXPIRED
+                info!(
+                    "registration failed, invalid invite for key: {} ({})",
+                    &key, peer
+                );
+                return send_message_and_close(writehalf, MessageType::NO).await;
+            }
+
+            // the invite stays valid unless the account is saved
+            match clients.register(&key, &password).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    // KEY WAS TAKEN IN THE MEANTIME
+                    relay.invites.release(&invite).await;
+                    info!(
+                        "registration failed, key already exists: {} ({})",
+                        &key, peer
+                    );
+                    return send_error_and_close(writehalf, MessageErrorKind::NotAvailable).await;
+                }
+                Err(err) => {
+                    relay.invites.release(&invite).await;
+                    return Err(err);
+                }
+            }
+            if let Err(err) = relay.invites.commit(&invite).await {
+                // stays reserved, so it can't be used again until the relay restarts
+                error!("failed to remove the used invite of {}: {}", &key, err);
+            }
+
+            // REGISTRATION CORRECT -> CONTINUE AS LOGGED IN
+            info!("registered and logged in: {} ({})", &key, peer);
+            Stats::increment(&stats.registrations);
+            (key, LoginOptions::default())
+        }
+        _ => {
+            // COMMAND SYNTAX IS CORRECT BUT ITS NOT A CONNECT CALL -> REFUSE
+            return send_error_and_close(writehalf, MessageErrorKind::ProtocolMismatch).await;
+        }
+    };
+
+    // add client connection
+    let connection_id = uuid::Uuid::new_v4().to_string();
+    let (tx, rx): (Tx, Rx) = outbox::channel(&relay.config.queue, Arc::clone(&relay.stats));
+
+    // add connection to the registry
+    let was_online = relay.registry.presence(&client_key).is_some();
+    let limits = &relay.config.connection_limits;
+    match limits.sessions_per_key {
+        Some(max_sessions) => match relay.registry.register_limited(
+            &client_key,
+            &connection_id,
+            options.device.as_deref(),
+            tx,
+            max_sessions,
+            limits.evict_oldest,
+        ) {
+            Ok(evicted) => {
+                for (evicted_id, channel) in evicted {
+                    // the connection closes once it has sent the error
+                    info!(
+                        "connection {} of {} evicted by a newer login",
+                        evicted_id, &client_key
+                    );
+                    Stats::increment(&stats.evicted_sessions);
+                    let _ = channel.send(MessageType::ERROR(MessageErrorKind::TooManySessions));
+                }
+            }
+            Err(_) => {
+                info!(
+                    "login refused, too many sessions: {} ({})",
+                    &client_key, peer
+                );
+                return send_error_and_close(writehalf, MessageErrorKind::TooManySessions).await;
+            }
+        },
+        None => relay
+            .registry
+            .register(&client_key, &connection_id, options.device.as_deref(), tx),
+    }
+    if options.ids {
+        relay.registry.enable_boop_ids(&client_key, &connection_id);
+    }
+
+    if !was_online {
+        relay.webhooks.send(WebhookEvent::Online {
+            key: client_key.clone(),
+        });
+    }
+
+    // queued until the welcome is sent
+    deliver_held_boops(relay, &client_key).await;
+
+    let keepalive = relay.config.keepalive.negotiate(options.keepalive);
+    let result =
+        match send_welcome(&mut writehalf, relay, options.keepalive.map(|_| keepalive)).await {
+            Ok(()) => {
+                relay_messages(
+                    &client_key,
+                    &connection_id,
+                    &options,
+                    reader,
+                    writehalf,
+                    rx,
+                    relay,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+
+    // remove connection from the registry, no matter how the connection ended
+    relay.registry.unregister(&client_key, &connection_id);
+    drop(permit);
+    if relay.registry.presence(&client_key).is_none() {
+        relay.webhooks.send(WebhookEvent::Offline {
+            key: client_key.clone(),
+        });
+        if let Err(err) = relay
+            .last_seen
+            .record(std::slice::from_ref(&client_key), unix_time())
+            .await
+        {
+            error!(
+                "failed to save the last-seen time of {}: {}",
+                client_key, err
+            );
+        }
+    }
+    result
+}
+
+/// Confirms the login, with the negotiated keepalive interval if the client asked for one.
+async fn send_welcome<W: AsyncWrite + Unpin>(
+    writehalf: &mut W,
+    relay: &Relay,
+    keepalive: Option<u64>,
+) -> io::Result<()> {
+    send_message(writehalf, MessageType::HEY).await?;
+    if let Some(secs) = keepalive {
+        send_message(writehalf, MessageType::KEEPALIVE(secs)).await?;
+    }
+    for line in relay.motd.lines().await {
+        send_message(writehalf, MessageType::NOTICE(line)).await?;
+    }
+
+    Ok(())
+}
+
+/// The instant after the duration, capped for intervals configured absurdly long.
+fn deadline_after(duration: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(duration)
+        .unwrap_or_else(|| now + FAR_FUTURE)
+}
+
+async fn relay_messages<S>(
+    client_key: &str,
+    connection_id: &str,
+    options: &LoginOptions,
+    mut reader: BufReader<ReadHalf<S>>,
+    mut writehalf: WriteHalf<S>,
+    mut rx: Rx,
+    relay: &Relay,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let stats = &relay.stats;
+    let mut limits = relay.limiter.connection_limiter();
+    let keepalive = Duration::from_secs(relay.config.keepalive.negotiate(options.keepalive));
+    let ping_timeout = relay.config.keepalive.ping_timeout();
+    // any command counts as activity and restarts the watchdog
+    let watchdog = tokio::time::sleep(keepalive);
+    tokio::pin!(watchdog);
+    let mut was_pinged = false;
+
+    loop {
+        let mut buf = String::new();
+        tokio::select! {
+            _ = &mut watchdog => match ping_timeout {
+                Some(ping_timeout) if !was_pinged => {
+                    send_message(&mut writehalf, MessageType::PING).await?;
+                    was_pinged = true;
+                    watchdog.as_mut().reset(deadline_after(ping_timeout));
+                },
+                _ => {
+                    debug!("connection {} timed out", connection_id);
+                    return writehalf.shutdown().await;
+                }
+            },
+            res = reader.read_line(&mut buf) => match res {
+                Ok(n) => {
+                    if n == 0 { //EOF while reading
+                        return Err(Error::from(io::ErrorKind::UnexpectedEof));
+                    }
+                    was_pinged = false;
+                    watchdog.as_mut().reset(deadline_after(keepalive));
+
+                    debug!("{}", &buf);
+                    let parse_result = parse_message(&buf);
+                    if let Ok(msg) = parse_result {
+                        if msg != MessageType::DISCONNECT {
+                            match relay.limiter.check(client_key, &mut limits, CommandKind::from(&msg)) {
+                                Verdict::Allowed => {},
+                                Verdict::Limited => {
+                                    Stats::increment(&stats.rate_limited);
+                                    send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::RateLimited)).await?;
+                                    continue;
+                                },
+                                Verdict::Disconnect => {
+                                    info!("connection {} of {} exceeded the rate limits... closing", connection_id, client_key);
+                                    Stats::increment(&stats.rate_limit_disconnects);
+                                    return send_error_and_close(writehalf, MessageErrorKind::RateLimited).await;
+                                }
+                            }
+                        }
+
+                        match msg {
+                            MessageType::DISCONNECT => {
+                                return send_message_and_close(writehalf, MessageType::BYE).await;
+                            },
+                            MessageType::PING => {
+                                send_message(&mut writehalf, MessageType::PONG).await?;
+                            },
+                            MessageType::PONG => {}, // answer to a keepalive ping
+                            MessageType::BOOP(partner, None) => {
+                                if let Some(answer) = relay_boop(relay, client_key, &partner, None).await {
+                                    send_message(&mut writehalf, answer).await?;
+                                }
+                            },
+                            MessageType::BOOPBACK(None, id) => {
+                                // only the recipient of the boop can answer it
+                                let sender = match relay.recent_boops.sender(id, client_key) {
+                                    Some(sender) => sender,
+                                    None => {
+                                        send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                        continue;
+                                    }
+                                };
+
+                                if let Some(answer) = relay_boop(relay, client_key, &sender, Some(id)).await {
+                                    send_message(&mut writehalf, answer).await?;
+                                }
+                            },
+                            MessageType::SCHEDULE(partner, time) => {
+                                let now = unix_time();
+                                let time = time.resolve(now);
+                                let config = &relay.config.scheduled;
+                                if time > now.saturating_add(config.max_delay()) {
+                                    send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::MalformedArguments)).await?;
+                                    continue;
+                                }
+
+                                match relay.scheduled.add(client_key, &partner, time, config.max_pending).await {
+                                    Ok(Some(boop)) => {
+                                        Stats::increment(&stats.boops_scheduled);
+                                        send_message(&mut writehalf, MessageType::SCHEDULED(boop.id, boop.to, boop.time)).await?;
+                                    },
+                                    Ok(None) => {
+                                        send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::RateLimited)).await?;
+                                    },
+                                    Err(err) => {
+                                        error!("failed to save the scheduled boops of {}: {}", client_key, err);
+                                        send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                    }
+                                }
+                            },
+                            MessageType::PENDING(None) => {
+                                let boops = relay.scheduled.pending(client_key).await;
+                                send_message(&mut writehalf, MessageType::PENDING(Some(boops.len() as u32))).await?;
+                                for boop in boops {
+                                    send_message(&mut writehalf, MessageType::SCHEDULED(boop.id, boop.to, boop.time)).await?;
+                                }
+                            },
+                            MessageType::CANCEL(id) => match relay.scheduled.cancel(client_key, id).await {
+                                Ok(true) => {},
+                                Ok(false) => {
+                                    send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                },
+                                Err(err) => {
+                                    error!("failed to save the scheduled boops of {}: {}", client_key, err);
+                                    send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                }
+                            },
+                            MessageType::AYT(partner_key) => {
+                                Stats::increment(&stats.presence_checks);
+
+                                // blocked keys see the partner as offline
+                                let blocked = relay.settings.blocks(&partner_key, client_key).await;
+                                match relay.registry.presence(&partner_key).filter(|_| !blocked) {
+                                    Some(status) => {
+                                        send_message(&mut writehalf, MessageType::ONLINE(partner_key.clone(), Vec::new())).await?;
+                                        if options.presence {
+                                            send_message(&mut writehalf, MessageType::PRESENCE(partner_key, status.state, status.text)).await?;
+                                        }
+                                    },
+                                    None => {
+                                        let last_seen = last_seen(relay, &partner_key, client_key).await;
+                                        send_message(&mut writehalf, MessageType::AFK(partner_key, last_seen)).await?;
+                                    },
+                                }
+                            },
+                            MessageType::DEVICES(partner_key) => {
+                                Stats::increment(&stats.presence_checks);
+
+                                let blocked = relay.settings.blocks(&partner_key, client_key).await;
+                                match (relay.registry.devices(&partner_key).filter(|_| !blocked), relay.registry.presence(&partner_key)) {
+                                    (Some(devices), Some(status)) => {
+                                        send_message(&mut writehalf, MessageType::ONLINE(partner_key.clone(), devices)).await?;
+                                        if options.presence {
+                                            send_message(&mut writehalf, MessageType::PRESENCE(partner_key, status.state, status.text)).await?;
+                                        }
+                                    },
+                                    _ => {
+                                        let last_seen = last_seen(relay, &partner_key, client_key).await;
+                                        send_message(&mut writehalf, MessageType::AFK(partner_key, last_seen)).await?;
+                                    },
+                                }
+                            },
+                            MessageType::STATUS(state, text) => {
+                                relay.registry.set_status(client_key, connection_id, Status::new(state, text));
+                            },
+                            MessageType::BLOCK(partner_key) => {
+                                if let Err(err) = relay.settings.set_blocked(client_key, &partner_key, true).await {
+                                    error!("failed to save the block list of {}: {}", client_key, err);
+                                    send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                }
+                            },
+                            MessageType::UNBLOCK(partner_key) => {
+                                if let Err(err) = relay.settings.set_blocked(client_key, &partner_key, false).await {
+                                    error!("failed to save the block list of {}: {}", client_key, err);
+                                    send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                }
+                            },
+                            MessageType::BLOCKED(keys) if keys.is_empty() => {
+                                send_message(&mut writehalf, MessageType::BLOCKED(relay.settings.blocked(client_key).await)).await?;
+                            },
+                            MessageType::HISTORY(partner_key, since) => {
+                                let history = match &relay.history {
+                                    Some(history) => history,
+                                    None => {
+                                        send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                        continue;
+                                    }
+                                };
+
+                                match history.query(client_key, &partner_key, since).await {
+                                    Ok(entries) => {
+                                        send_message(&mut writehalf, MessageType::ENTRIES(partner_key, entries.len() as u32)).await?;
+                                        for entry in entries {
+                                            send_message(&mut writehalf, MessageType::ENTRY(entry)).await?;
+                                        }
+                                    },
+                                    Err(err) => {
+                                        error!("failed to read the boop history of {}: {}", client_key, err);
+                                        send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                    }
+                                }
+                            },
+                            MessageType::STATS(partner_key) => {
+                                let now = unix_time();
+                                let sent = relay.pair_stats.get(client_key, &partner_key, now).await;
+                                let received = relay.pair_stats.get(&partner_key, client_key, now).await;
+                                send_message(&mut writehalf, MessageType::COUNTS(sent)).await?;
+                                send_message(&mut writehalf, MessageType::COUNTS(received)).await?;
+                            },
+                            MessageType::LASTSEEN(share) => {
+                                if let Err(err) = relay.settings.set_hide_last_seen(client_key, !share).await {
+                                    error!("failed to save the last-seen setting of {}: {}", client_key, err);
+                                    send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                }
+                            },
+                            MessageType::DND(command) => {
+                                let mut dnd = relay.settings.dnd(client_key).await;
+                                match command {
+                                    DndCommand::Enabled(enabled) => dnd.enabled = enabled,
+                                    DndCommand::QuietHours(quiet_hours) => dnd.quiet_hours = quiet_hours,
+                                    DndCommand::NotifySenders(notify) => dnd.notify_senders = notify,
+                                }
+
+                                match relay.settings.set_dnd(client_key, dnd).await {
+                                    Ok(_) => deliver_held_boops(relay, client_key).await,
+                                    Err(err) => {
+                                        error!("failed to save the do-not-disturb settings of {}: {}", client_key, err);
+                                        send_message(&mut writehalf, MessageType::ERROR(MessageErrorKind::NotAvailable)).await?;
+                                    }
+                                }
+                            },
+                            _ => {
+                                // against protocol -> disconnect
+                                return send_error_and_close(writehalf, MessageErrorKind::ProtocolMismatch).await;
+                            }
+                        }
+                    }
+                    else { //close connection on non-compliant message
+                        return send_error_and_close(writehalf, parse_result.unwrap_err().into()).await;
+                    }
+                },
+                Err(err) => { //close connection on read error
+                    error!("there was an error reading from the connection ({})... closing", &err);
+                    return writehalf.shutdown().await;
+                },
+            },
+            msg = rx.recv() => match msg {
+                Some(msg) => {
+                    send_message(&mut writehalf, msg).await?;
+                },
+                None if rx.overflowed() => { //client doesn't read its messages fast enough
+                    info!("connection {} of {} can't keep up with its messages... closing", connection_id, client_key);
+                    return writehalf.shutdown().await;
+                },
+                None => { //channel was dropped from the state -> connection was kicked
+                    info!("connection {} was closed by the relay", connection_id);
+                    return writehalf.shutdown().await;
+                }
+            }
+        }
+    }
+}
+
+/// When the offline key was last online, unless it hides it or blocked the asking key.
+async fn last_seen(relay: &Relay, key: &str, asking_key: &str) -> Option<u64> {
+    if relay.settings.hides_last_seen(key).await || relay.settings.blocks(key, asking_key).await {
+        None
+    } else {
+        relay.last_seen.get(key).await
+    }
+}
+
+async fn send_error_and_close<W: AsyncWrite + Unpin>(
+    writehalf: W,
+    err: MessageErrorKind,
+) -> io::Result<()> {
+    send_message_and_close(writehalf, MessageType::ERROR(err)).await
+}
+
+async fn send_message_and_close<W: AsyncWrite + Unpin>(
+    mut writehalf: W,
+    message: MessageType,
+) -> io::Result<()> {
+    send_message(&mut writehalf, message).await?;
+    writehalf.shutdown().await
+}
+
+async fn send_message<W: AsyncWrite + Unpin>(
+    writehalf: &mut W,
+    message: MessageType,
+) -> io::Result<()> {
+    let msg_text = create_message_text(message);
+    writehalf.write_all(msg_text.as_bytes()).await
+}
+
+#[cfg(unix)]
+fn start_admin_socket(path: &Path, relay: &Arc<Relay>) -> io::Result<JoinHandle<()>> {
+    let listener = crate::admin::bind(path)?;
+    info!("admin interface listening on {}", path.display());
+
+    Ok(tokio::spawn(crate::admin::serve(
+        listener,
+        Arc::clone(relay),
+    )))
+}
+
+#[cfg(not(unix))]
+fn start_admin_socket(_path: &Path, _relay: &Arc<Relay>) -> io::Result<JoinHandle<()>> {
+    warn!("the admin interface is only available on unix systems");
+    Ok(tokio::spawn(async {}))
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::resolve;
+
+    #[test]
+    fn test_resolve_all_addresses() {
+        let addrs = resolve("localhost:6969").unwrap();
+        assert!(addrs.contains(&"127.0.0.1:6969".parse().unwrap()));
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+
+        assert_eq!(
+            resolve("[::1]:6969").unwrap(),
+            vec!["[::1]:6969".parse().unwrap()]
+        );
+        assert!(resolve("no port").is_err());
+    }
+}
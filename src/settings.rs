@@ -0,0 +1,190 @@
+use std::{
+    collections::BTreeMap,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::RwLock};
+
+use crate::dnd::DndSettings;
+
+/// Settings a client changes for itself, kept apart from the operator's clients file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct UserSettings {
+    #[serde(skip_serializing_if = "DndSettings::is_default")]
+    pub dnd: DndSettings,
+    /// Answer presence checks with `AFK <key>` only, without the last-seen time.
+    #[serde(skip_serializing_if = "is_false")]
+    pub hide_last_seen: bool,
+    /// Keys whose boops and presence checks are answered as if this client was offline.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blocked: Vec<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Settings per key, backed by the settings file.
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: RwLock<BTreeMap<String, UserSettings>>,
+}
+
+impl SettingsStore {
+    /// Reads the settings file, a missing file is an empty store.
+    pub async fn open(path: &Path) -> Result<SettingsStore, Error> {
+        let settings = read_settings_file(path).await?;
+
+        Ok(SettingsStore {
+            path: path.to_path_buf(),
+            settings: RwLock::new(settings),
+        })
+    }
+
+    /// Do-not-disturb settings of the key, the defaults if it never changed them.
+    pub async fn dnd(&self, key: &str) -> DndSettings {
+        self.settings
+            .read()
+            .await
+            .get(key)
+            .map(|settings| settings.dnd.clone())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the do-not-disturb settings of the key.
+    pub async fn set_dnd(&self, key: &str, dnd: DndSettings) -> Result<(), Error> {
+        self.update(key, |settings| settings.dnd = dnd).await
+    }
+
+    pub async fn hides_last_seen(&self, key: &str) -> bool {
+        self.settings
+            .read()
+            .await
+            .get(key)
+            .is_some_and(|settings| settings.hide_last_seen)
+    }
+
+    /// Hides or shares the last-seen time of the key.
+    pub async fn set_hide_last_seen(&self, key: &str, hide: bool) -> Result<(), Error> {
+        self.update(key, |settings| settings.hide_last_seen = hide)
+            .await
+    }
+
+    /// Whether `key` blocked `other`.
+    pub async fn blocks(&self, key: &str, other: &str) -> bool {
+        self.settings
+            .read()
+            .await
+            .get(key)
+            .is_some_and(|settings| settings.blocked.iter().any(|blocked| blocked == other))
+    }
+
+    /// Keys blocked by the key, in the order they were blocked.
+    pub async fn blocked(&self, key: &str) -> Vec<String> {
+        self.settings
+            .read()
+            .await
+            .get(key)
+            .map(|settings| settings.blocked.clone())
+            .unwrap_or_default()
+    }
+
+    /// Blocks or unblocks `other` for the key.
+    pub async fn set_blocked(&self, key: &str, other: &str, block: bool) -> Result<(), Error> {
+        self.update(key, |settings| {
+            settings.blocked.retain(|blocked| blocked != other);
+            if block {
+                settings.blocked.push(String::from(other));
+            }
+        })
+        .await
+    }
+
+    /// Changes the settings of the key and writes the updated file, or restores the previous
+    /// settings if that fails. Keys back at the defaults are dropped from the file.
+    async fn update(&self, key: &str, change: impl FnOnce(&mut UserSettings)) -> Result<(), Error> {
+        let mut settings = self.settings.write().await;
+
+        let previous = settings.get(key).cloned();
+        let mut changed = previous.clone().unwrap_or_default();
+        change(&mut changed);
+        if changed == UserSettings::default() {
+            settings.remove(key);
+        } else {
+            settings.insert(String::from(key), changed);
+        }
+
+        if let Err(err) = write_settings_file(&self.path, &settings).await {
+            match previous {
+                Some(previous) => settings.insert(String::from(key), previous),
+                None => settings.remove(key),
+            };
+            return Err(err);
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn read_settings_file(path: &Path) -> Result<BTreeMap<String, UserSettings>, Error> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let contents = fs::read_to_string(path).await?;
+    let settings: BTreeMap<String, UserSettings> = serde_json::from_str(contents.as_str())?;
+
+    Ok(settings)
+}
+
+pub async fn write_settings_file(
+    path: &Path,
+    settings: &BTreeMap<String, UserSettings>,
+) -> Result<(), Error> {
+    let contents = serde_json::to_string_pretty(settings)?;
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::{read_settings_file, SettingsStore};
+
+    #[tokio::test]
+    async fn test_settings_persisted() {
+        let path =
+            std::env::temp_dir().join(format!("boop-settings-{}.json", uuid::Uuid::new_v4()));
+
+        let store = SettingsStore::open(&path).await.unwrap();
+        assert!(!store.blocks("foo", "bar").await);
+
+        store.set_blocked("foo", "bar", true).await.unwrap();
+        store.set_blocked("foo", "baz", true).await.unwrap();
+        store.set_hide_last_seen("foo", true).await.unwrap();
+        store.set_hide_last_seen("qux", true).await.unwrap();
+
+        let store = SettingsStore::open(&path).await.unwrap();
+        assert!(store.blocks("foo", "bar").await);
+        assert!(!store.blocks("bar", "foo").await);
+        assert_eq!(store.blocked("foo").await, vec!["bar", "baz"]);
+        assert!(store.hides_last_seen("foo").await);
+
+        // keys back at the defaults are dropped from the file
+        store.set_hide_last_seen("qux", false).await.unwrap();
+        let settings = read_settings_file(&path).await.unwrap();
+        assert_eq!(settings.keys().collect::<Vec<_>>(), vec!["foo"]);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
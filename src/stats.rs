@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Relay-wide counters, reported by the admin socket.
+#[derive(Default)]
+pub struct Stats {
+    pub connections_accepted: AtomicU64,
+    pub logins: AtomicU64,
+    pub failed_logins: AtomicU64,
+    pub registrations: AtomicU64,
+    pub boops_relayed: AtomicU64,
+    pub presence_checks: AtomicU64,
+    pub kicked_connections: AtomicU64,
+    pub rate_limited: AtomicU64,
+    pub rate_limit_disconnects: AtomicU64,
+    pub dropped_messages: AtomicU64,
+    pub coalesced_messages: AtomicU64,
+    pub slow_client_disconnects: AtomicU64,
+    pub refused_connections: AtomicU64,
+    pub evicted_sessions: AtomicU64,
+    pub boops_held: AtomicU64,
+    pub boops_scheduled: AtomicU64,
+    pub webhooks_sent: AtomicU64,
+    pub webhooks_failed: AtomicU64,
+    pub webhooks_dropped: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Name and current value of every counter.
+    pub fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("connections_accepted", &self.connections_accepted),
+            ("logins", &self.logins),
+            ("failed_logins", &self.failed_logins),
+            ("registrations", &self.registrations),
+            ("boops_relayed", &self.boops_relayed),
+            ("presence_checks", &self.presence_checks),
+            ("kicked_connections", &self.kicked_connections),
+            ("rate_limited", &self.rate_limited),
+            ("rate_limit_disconnects", &self.rate_limit_disconnects),
+            ("dropped_messages", &self.dropped_messages),
+            ("coalesced_messages", &self.coalesced_messages),
+            ("slow_client_disconnects", &self.slow_client_disconnects),
+            ("refused_connections", &self.refused_connections),
+            ("evicted_sessions", &self.evicted_sessions),
+            ("boops_held", &self.boops_held),
+            ("boops_scheduled", &self.boops_scheduled),
+            ("webhooks_sent", &self.webhooks_sent),
+            ("webhooks_failed", &self.webhooks_failed),
+            ("webhooks_dropped", &self.webhooks_dropped),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name, counter.load(Ordering::Relaxed)))
+        .collect()
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    io::{self, Error},
+    sync::Arc,
+    time::Duration,
+};
+
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    rustls::{self, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+use crate::{stats::Stats, unix_time};
+
+/// Header with the hex encoded HMAC-SHA256 of the request body, e.g. `sha256=1f0e...`.
+pub const SIGNATURE_HEADER: &str = "X-Boop-Signature";
+
+/// HTTP endpoints that are notified of relay events.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub endpoints: Vec<WebhookEndpoint>,
+    /// Events waiting per endpoint, new events are dropped while the queue is full.
+    pub queue_capacity: usize,
+    /// Deliveries of an event before it is given up.
+    pub max_attempts: u32,
+    /// Seconds before the first retry, doubled after every failed attempt up to
+    /// `max_retry_delay`.
+    pub retry_delay: u64,
+    pub max_retry_delay: u64,
+    /// Seconds a single request may take.
+    pub timeout: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> WebhookConfig {
+        WebhookConfig {
+            endpoints: Vec::new(),
+            queue_capacity: 1000,
+            max_attempts: 5,
+            retry_delay: 1,
+            max_retry_delay: 60,
+            timeout: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookEndpoint {
+    /// `http://` or `https://` URL the events are posted to.
+    pub url: String,
+    /// Key of the signature in the `X-Boop-Signature` header.
+    pub secret: String,
+    /// Events posted to the endpoint, all of them if empty.
+    #[serde(default)]
+    pub events: Vec<WebhookEventKind>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    BoopRelayed,
+    BoopUndeliverable,
+    Online,
+    Offline,
+    LoginFailed,
+}
+
+/// Something that happened on the relay. Posted as a JSON object with the `event` name,
+/// the fields of the event and a `timestamp`, e.g.
+/// `{"event":"online","key":"foo","timestamp":1656676800}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A boop (or boop-back) reached at least one connection of the recipient.
+    BoopRelayed {
+        from: String,
+        to: String,
+        device: Option<String>,
+    },
+    /// A boop to an offline key or device.
+    BoopUndeliverable {
+        from: String,
+        to: String,
+        device: Option<String>,
+    },
+    /// The first connection of a key logged in.
+    Online { key: String },
+    /// The last connection of a key closed.
+    Offline { key: String },
+    /// A login with a wrong password or a banned key.
+    LoginFailed { key: String, addr: String },
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> WebhookEventKind {
+        match self {
+            WebhookEvent::BoopRelayed { .. } => WebhookEventKind::BoopRelayed,
+            WebhookEvent::BoopUndeliverable { .. } => WebhookEventKind::BoopUndeliverable,
+            WebhookEvent::Online { .. } => WebhookEventKind::Online,
+            WebhookEvent::Offline { .. } => WebhookEventKind::Offline,
+            WebhookEvent::LoginFailed { .. } => WebhookEventKind::LoginFailed,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    timestamp: u64,
+}
+
+/// Hex encoded HMAC-SHA256 of the body, the value of the signature header after `sha256=`.
+pub fn sign(secret: &str, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, body.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Queues of the webhook endpoints, each delivered by its own task so a slow endpoint
+/// neither blocks the relay nor the other endpoints.
+pub struct Webhooks {
+    endpoints: Vec<(Vec<WebhookEventKind>, mpsc::Sender<String>)>,
+    tasks: Vec<JoinHandle<()>>,
+    stats: Arc<Stats>,
+}
+
+impl Webhooks {
+    /// Starts the delivery tasks. Fails if the URL of an endpoint is invalid.
+    pub fn start(config: &WebhookConfig, stats: Arc<Stats>) -> Result<Webhooks, Error> {
+        let mut endpoints = Vec::new();
+        let mut tasks = Vec::new();
+        let connector = tls_connector();
+        for endpoint in &config.endpoints {
+            let url = Url::parse(&endpoint.url)?;
+            let (queue, queue_rx) = mpsc::channel(config.queue_capacity.max(1));
+            let delivery = Delivery {
+                url,
+                secret: endpoint.secret.clone(),
+                connector: connector.clone(),
+                config: config.clone(),
+                stats: Arc::clone(&stats),
+            };
+
+            endpoints.push((endpoint.events.clone(), queue));
+            tasks.push(tokio::spawn(delivery.run(queue_rx)));
+        }
+
+        Ok(Webhooks {
+            endpoints,
+            tasks,
+            stats,
+        })
+    }
+
+    /// Queues the event for every endpoint that wants it, without waiting for deliveries.
+    pub fn send(&self, event: WebhookEvent) {
+        if self.endpoints.is_empty() {
+            return;
+        }
+
+        let kind = event.kind();
+        let payload = Payload {
+            event: &event,
+            timestamp: unix_time(),
+        };
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("failed to serialize a webhook event: {}", err);
+                return;
+            }
+        };
+
+        for (events, queue) in &self.endpoints {
+            if !events.is_empty() && !events.contains(&kind) {
+                continue;
+            }
+
+            if let Err(TrySendError::Full(_)) = queue.try_send(body.clone()) {
+                Stats::increment(&self.stats.webhooks_dropped);
+            }
+        }
+    }
+
+    /// Stops the deliveries, queued events are dropped.
+    pub fn stop(&self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+fn tls_connector() -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+}
+
+/// The parts of a webhook URL needed for a request.
+#[derive(Debug, PartialEq)]
+struct Url {
+    tls: bool,
+    host: String, // IPv6 addresses in brackets
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, Error> {
+        let invalid = || Error::new(io::ErrorKind::InvalidInput, format!("invalid URL: {}", url));
+
+        let (tls, rest) = match url.split_once("://") {
+            Some(("http", rest)) => (false, rest),
+            Some(("https", rest)) => (true, rest),
+            _ => return Err(invalid()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+
+        // the port follows the last colon, unless it's part of an IPv6 address
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.ends_with(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Url {
+            tls,
+            host: String::from(host),
+            port,
+            path: String::from(path),
+        })
+    }
+
+    fn host_header(&self) -> String {
+        match (self.tls, self.port) {
+            (false, 80) | (true, 443) => self.host.clone(),
+            _ => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Posts the events of one endpoint, one after another.
+struct Delivery {
+    url: Url,
+    secret: String,
+    connector: TlsConnector,
+    config: WebhookConfig,
+    stats: Arc<Stats>,
+}
+
+impl Delivery {
+    async fn run(self, mut queue: mpsc::Receiver<String>) {
+        let timeout = Duration::from_secs(self.config.timeout);
+        let max_retry_delay = Duration::from_secs(self.config.max_retry_delay);
+
+        while let Some(body) = queue.recv().await {
+            let mut retry_delay = Duration::from_secs(self.config.retry_delay);
+            for attempt in 1..=self.config.max_attempts.max(1) {
+                let result = match tokio::time::timeout(timeout, self.post(&body)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(Error::new(io::ErrorKind::TimedOut, "request timed out")),
+                };
+                match result {
+                    Ok(()) => {
+                        Stats::increment(&self.stats.webhooks_sent);
+                        break;
+                    }
+                    Err(err) if attempt >= self.config.max_attempts => {
+                        error!("giving up on webhook {}: {}", self.url.host, err);
+                        Stats::increment(&self.stats.webhooks_failed);
+                    }
+                    Err(err) => {
+                        warn!("webhook {} failed, retrying: {}", self.url.host, err);
+                        tokio::time::sleep(retry_delay).await;
+                        retry_delay = (retry_delay * 2).min(max_retry_delay);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn post(&self, body: &str) -> Result<(), Error> {
+        let host = self.url.host.trim_matches(&['[', ']'][..]);
+        let stream = TcpStream::connect((host, self.url.port)).await?;
+        if !self.url.tls {
+            return self.request(stream, body).await;
+        }
+
+        let server_name = ServerName::try_from(host)
+            .map_err(|err| Error::new(io::ErrorKind::InvalidInput, err))?;
+        let stream = self.connector.connect(server_name, stream).await?;
+        self.request(stream, body).await
+    }
+
+    /// Sends the request and checks the status of the response, the rest of it is ignored.
+    async fn request<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut stream: S,
+        body: &str,
+    ) -> Result<(), Error> {
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}: sha256={}\r\nConnection: close\r\n\r\n",
+            self.url.path,
+            self.url.host_header(),
+            body.len(),
+            SIGNATURE_HEADER,
+            sign(&self.secret, body)
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.flush().await?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line).await?;
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| Error::new(io::ErrorKind::InvalidData, "invalid HTTP response"))?;
+
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(Error::other(format!("HTTP status {}", status)))
+        }
+    }
+}
+
+/*
+    #######################################################################################
+    ######################################## TESTS ########################################
+    #######################################################################################
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, Url, WebhookEvent};
+
+    #[test]
+    fn test_webhook_url() {
+        assert_eq!(
+            Url::parse("https://hooks.example.com/boop?token=1").unwrap(),
+            Url {
+                tls: true,
+                host: String::from("hooks.example.com"),
+                port: 443,
+                path: String::from("/boop?token=1"),
+            }
+        );
+
+        let url = Url::parse("http://[::1]:8080").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 8080));
+        assert_eq!(url.path, "/");
+        assert_eq!(url.host_header(), "[::1]:8080");
+        let url = Url::parse("http://[::1]/hook").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 80));
+        assert_eq!(url.host_header(), "[::1]");
+
+        assert!(Url::parse("ftp://example.com").is_err());
+        assert!(Url::parse("example.com:80").is_err());
+        assert!(Url::parse("http://example.com:http/").is_err());
+        assert!(Url::parse("http:///hook").is_err());
+    }
+
+    #[test]
+    fn test_webhook_event_json() {
+        let event = WebhookEvent::BoopRelayed {
+            from: String::from("foo"),
+            to: String::from("bar"),
+            device: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&super::Payload {
+                event: &event,
+                timestamp: 1656676800
+            })
+            .unwrap(),
+            r#"{"event":"boop_relayed","from":"foo","to":"bar","device":null,"timestamp":1656676800}"#
+        );
+    }
+
+    #[test]
+    fn test_webhook_signature() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
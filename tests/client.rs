@@ -0,0 +1,198 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use boop_relay::{
+    client::{ClientBuilder, ClientError, Event},
+    config::RelayConfig,
+    message::{MessageErrorKind, PresenceState},
+};
+use common::{TestRelay, PASSWORD};
+
+fn client(relay: &TestRelay, key: &str, password: &str) -> ClientBuilder {
+    ClientBuilder::new(&relay.addr().to_string(), key, password)
+        .server_name("localhost")
+        .tls_config(Arc::clone(&relay.client_tls))
+        .backoff(Duration::from_millis(50), Duration::from_millis(200))
+}
+
+#[tokio::test]
+async fn test_client_boop_and_presence() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let (foo, _foo_events) = client(&relay, "foo", PASSWORD).connect().await.unwrap();
+    assert!(!foo.ayt("foo2").await.unwrap());
+
+    let (foo2, mut foo2_events) = client(&relay, "foo2", PASSWORD).connect().await.unwrap();
+    assert!(foo.ayt("foo2").await.unwrap());
+
+    foo.boop("foo2").await.unwrap();
+    assert!(matches!(
+        foo2_events.recv().await,
+        Some(Event::Boop { from, count: 1, id: Some(_) }) if from == "foo"
+    ));
+
+    foo2.disconnect().await.unwrap();
+    assert_eq!(foo2_events.recv().await, None);
+    assert!(!foo.ayt("foo2").await.unwrap());
+
+    foo.disconnect().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_client_ayt_rate_limited() {
+    let relay = TestRelay::start_with(&["foo", "foo2"], |builder, _| {
+        let config: RelayConfig = serde_json::from_str(
+            r#"{ "rate_limits": { "connection": { "ayt": { "rate": 0.001, "burst": 1 } } } }"#,
+        )
+        .unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let (foo, _foo_events) = client(&relay, "foo", PASSWORD).connect().await.unwrap();
+    assert!(!foo.ayt("foo2").await.unwrap());
+
+    let result = tokio::time::timeout(Duration::from_secs(5), foo.ayt("foo2")).await;
+    assert!(matches!(
+        result,
+        Ok(Err(ClientError::Relay(MessageErrorKind::RateLimited)))
+    ));
+    let result = tokio::time::timeout(Duration::from_secs(5), foo.devices("foo2")).await;
+    assert!(matches!(
+        result,
+        Ok(Err(ClientError::Relay(MessageErrorKind::RateLimited)))
+    ));
+
+    foo.disconnect().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_client_boop_back() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let (foo, mut foo_events) = client(&relay, "foo", PASSWORD).connect().await.unwrap();
+    let (foo2, mut foo2_events) = client(&relay, "foo2", PASSWORD).connect().await.unwrap();
+
+    foo.boop("foo2").await.unwrap();
+    let id = match foo2_events.recv().await {
+        Some(Event::Boop {
+            from,
+            count: 1,
+            id: Some(id),
+        }) if from == "foo" => id,
+        event => panic!("unexpected event: {:?}", event),
+    };
+
+    foo2.boop_back(id).await.unwrap();
+    assert_eq!(
+        foo_events.recv().await,
+        Some(Event::BoopBack {
+            from: String::from("foo2"),
+            id
+        })
+    );
+
+    foo.disconnect().await.unwrap();
+    foo2.disconnect().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_client_login_refused() {
+    let relay = TestRelay::start(&["foo"]).await;
+
+    let result = client(&relay, "foo", "wrong").connect().await;
+    assert!(matches!(result, Err(ClientError::LoginRefused)));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_client_reconnects() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let (foo, mut foo_events) = client(&relay, "foo", PASSWORD).connect().await.unwrap();
+
+    let relay = relay.restart().await;
+    assert_eq!(foo_events.recv().await, Some(Event::Disconnected));
+    assert_eq!(foo_events.recv().await, Some(Event::Reconnected));
+
+    let (foo2, _foo2_events) = client(&relay, "foo2", PASSWORD).connect().await.unwrap();
+    assert!(foo2.ayt("foo").await.unwrap());
+    foo2.boop("foo").await.unwrap();
+    assert!(matches!(
+        foo_events.recv().await,
+        Some(Event::Boop { from, count: 1, id: Some(_) }) if from == "foo2"
+    ));
+
+    foo.disconnect().await.unwrap();
+    foo2.disconnect().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_client_devices() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let (foo, _foo_events) = client(&relay, "foo", PASSWORD).connect().await.unwrap();
+    assert_eq!(foo.devices("foo2").await.unwrap(), None);
+
+    let (foo2, mut foo2_events) = client(&relay, "foo2", PASSWORD)
+        .device("phone")
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(
+        foo.devices("foo2").await.unwrap(),
+        Some(vec![String::from("phone")])
+    );
+
+    foo.boop("foo2/phone").await.unwrap();
+    assert!(matches!(
+        foo2_events.recv().await,
+        Some(Event::Boop { from, count: 1, id: Some(_) }) if from == "foo"
+    ));
+
+    foo.disconnect().await.unwrap();
+    foo2.disconnect().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_client_status() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let (foo, mut foo_events) = client(&relay, "foo", PASSWORD).connect().await.unwrap();
+    let (foo2, _foo2_events) = client(&relay, "foo2", PASSWORD).connect().await.unwrap();
+
+    foo2.set_status(PresenceState::DoNotDisturb, Some("focusing"))
+        .await
+        .unwrap();
+    // commands are sent in order, so the status is set before the check
+    foo2.ayt("foo2").await.unwrap();
+
+    assert!(foo.ayt("foo2").await.unwrap());
+    assert_eq!(
+        foo_events.recv().await,
+        Some(Event::Presence {
+            key: String::from("foo2"),
+            online: true,
+            last_seen: None
+        })
+    );
+    assert_eq!(
+        foo_events.recv().await,
+        Some(Event::Status {
+            key: String::from("foo2"),
+            state: PresenceState::DoNotDisturb,
+            text: Some(String::from("focusing"))
+        })
+    );
+
+    foo.disconnect().await.unwrap();
+    foo2.disconnect().await.unwrap();
+    relay.stop().await;
+}
@@ -0,0 +1,118 @@
+#![allow(dead_code)]
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use boop_relay::{
+    clients::{hash_password, write_clients_file, Client},
+    ServerBuilder, ServerHandle,
+};
+use tokio_rustls::rustls::{self, Certificate, PrivateKey, RootCertStore};
+
+pub const PASSWORD: &str = "bar";
+
+/// Adds settings to the builder of a test relay, gets the relay's temporary directory.
+pub type Configure = fn(ServerBuilder, &Path) -> ServerBuilder;
+
+/// Relay on a random local port with a temporary clients file and a self-signed
+/// certificate for `localhost`.
+pub struct TestRelay {
+    pub server: ServerHandle,
+    pub server_tls: Arc<rustls::ServerConfig>,
+    pub client_tls: Arc<rustls::ClientConfig>,
+    pub dir: PathBuf,
+    configure: Configure,
+}
+
+impl TestRelay {
+    pub async fn start(keys: &[&str]) -> TestRelay {
+        TestRelay::start_with(keys, |builder, _| builder).await
+    }
+
+    pub async fn start_with(keys: &[&str], configure: Configure) -> TestRelay {
+        let dir = std::env::temp_dir().join(format!("boop-relay-test-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let clients: Vec<Client> = keys
+            .iter()
+            .map(|key| Client {
+                key: String::from(*key),
+                hash: hash_password(PASSWORD).unwrap(),
+                ..Client::default()
+            })
+            .collect();
+        write_clients_file(&dir.join("clients.json"), &clients)
+            .await
+            .unwrap();
+
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let cert_der = Certificate(cert.serialize_der().unwrap());
+        let server_tls = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert_der.clone()],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&cert_der).unwrap();
+        let client_tls = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let server_tls = Arc::new(server_tls);
+        let server = start_server(&dir, &server_tls, "127.0.0.1:0", configure).await;
+
+        TestRelay {
+            server,
+            server_tls,
+            client_tls: Arc::new(client_tls),
+            dir,
+            configure,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.server.local_addr().unwrap()
+    }
+
+    /// Shuts the relay down and starts it again on the same address.
+    pub async fn restart(self) -> TestRelay {
+        let addr = self.addr();
+        self.server.shutdown().await.unwrap();
+
+        TestRelay {
+            server: start_server(
+                &self.dir,
+                &self.server_tls,
+                &addr.to_string(),
+                self.configure,
+            )
+            .await,
+            ..self
+        }
+    }
+
+    pub async fn stop(self) {
+        self.server.shutdown().await.unwrap();
+        tokio::fs::remove_dir_all(&self.dir).await.unwrap();
+    }
+}
+
+async fn start_server(
+    dir: &Path,
+    tls: &Arc<rustls::ServerConfig>,
+    addr: &str,
+    configure: Configure,
+) -> ServerHandle {
+    let builder = ServerBuilder::new(dir.join("clients.json"))
+        .listen(addr)
+        .tls_config(Arc::clone(tls));
+    configure(builder, dir).start().await.unwrap()
+}
@@ -0,0 +1,1376 @@
+mod common;
+
+use std::{
+    convert::TryFrom,
+    sync::{Arc, OnceLock},
+};
+
+use boop_relay::{
+    clients::{read_clients_file, write_clients_file, Ban},
+    config::RelayConfig,
+    history::BoopKind,
+    invites::InviteStore,
+    message::{
+        create_message_text, parse_message, BoopTime, DndCommand, LoginOptions, MessageErrorKind,
+        MessageType, PresenceState,
+    },
+    pairstats::PairCounts,
+    webhooks::{sign, WebhookEvent},
+    ListenAddr, ServerBuilder,
+};
+use common::{TestRelay, PASSWORD};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream, UnixStream},
+    sync::mpsc,
+};
+use tokio_rustls::{rustls::ServerName, TlsConnector};
+
+struct TestClient {
+    reader: BufReader<Box<dyn AsyncRead + Unpin + Send>>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+}
+
+impl TestClient {
+    async fn connect(relay: &TestRelay) -> TestClient {
+        let stream = TcpStream::connect(relay.addr()).await.unwrap();
+        let stream = TlsConnector::from(Arc::clone(&relay.client_tls))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        TestClient::from_stream(stream)
+    }
+
+    fn from_stream<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S) -> TestClient {
+        let (reader, writer) = tokio::io::split(stream);
+
+        TestClient {
+            reader: BufReader::new(Box::new(reader)),
+            writer: Box::new(writer),
+        }
+    }
+
+    async fn send(&mut self, msg: MessageType) {
+        let text = create_message_text(msg);
+        self.writer.write_all(text.as_bytes()).await.unwrap();
+    }
+
+    /// Next message, `None` if the relay closed the connection.
+    async fn recv(&mut self) -> Option<MessageType> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(parse_message(&line).unwrap()),
+        }
+    }
+
+    /// Source of the next message, which must be a relayed boop without an ID.
+    async fn recv_boop(&mut self) -> String {
+        match self.recv().await {
+            Some(MessageType::BOOP(source, None)) => source,
+            msg => panic!("expected a boop, got {:?}", msg),
+        }
+    }
+}
+
+fn connect(key: &str) -> MessageType {
+    MessageType::CONNECT(
+        String::from(key),
+        String::from(PASSWORD),
+        LoginOptions::default(),
+    )
+}
+
+#[tokio::test]
+async fn test_server_relays_boops() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    foo.send(MessageType::AYT(String::from("foo2"))).await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::ONLINE(String::from("foo2"), Vec::new()))
+    );
+
+    foo.send(MessageType::BOOP(String::from("foo2"), None))
+        .await;
+    assert_eq!(foo2.recv_boop().await, "foo");
+
+    foo2.send(MessageType::DISCONNECT).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::BYE));
+
+    relay.stop().await;
+    assert_eq!(foo.recv().await, None);
+}
+
+#[tokio::test]
+async fn test_server_refuses_wrong_login() {
+    let relay = TestRelay::start(&["foo"]).await;
+
+    let mut client = TestClient::connect(&relay).await;
+    client
+        .send(MessageType::CONNECT(
+            String::from("foo"),
+            String::from("wrong"),
+            LoginOptions::default(),
+        ))
+        .await;
+    assert_eq!(client.recv().await, Some(MessageType::NO));
+    assert_eq!(client.recv().await, None);
+
+    assert_eq!(
+        relay
+            .server
+            .stats()
+            .failed_logins
+            .load(std::sync::atomic::Ordering::Relaxed),
+        1
+    );
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_register_taken_key_keeps_invite() {
+    let relay = TestRelay::start(&["foo"]).await;
+    let invites = InviteStore::new(&relay.dir.join("invites.json"));
+    let invite = invites.mint(1, 60).await.unwrap().remove(0).code;
+
+    let register = |key: &str| {
+        MessageType::REGISTER(invite.clone(), String::from(key), String::from(PASSWORD))
+    };
+    let mut client = TestClient::connect(&relay).await;
+    client.send(register("foo")).await;
+    assert_eq!(
+        client.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::NotAvailable))
+    );
+
+    let mut client = TestClient::connect(&relay).await;
+    client.send(register("foo2")).await;
+    assert_eq!(client.recv().await, Some(MessageType::HEY));
+
+    // used up now
+    let mut client = TestClient::connect(&relay).await;
+    client.send(register("foo3")).await;
+    assert_eq!(client.recv().await, Some(MessageType::NO));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_plaintext_listeners() {
+    let relay = TestRelay::start_with(&["foo", "foo2"], |builder, dir| {
+        builder
+            .listen_plain("127.0.0.1:0")
+            .listen_unix(dir.join("relay.sock"))
+    })
+    .await;
+
+    let mut foo = TestClient::from_stream(TcpStream::connect(plain_addr(&relay)).await.unwrap());
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let socket = relay.dir.join("relay.sock");
+    let mut foo2 = TestClient::from_stream(UnixStream::connect(&socket).await.unwrap());
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    assert_eq!(foo.recv_boop().await, "foo2");
+
+    relay.stop().await;
+    assert!(!socket.exists());
+}
+
+#[tokio::test]
+async fn test_server_plaintext_loopback_only() {
+    let result = ServerBuilder::new("clients.json")
+        .listen_plain("0.0.0.0:0")
+        .start()
+        .await;
+    match result {
+        Err(err) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+        Ok(_) => panic!("plaintext listener on a public address was accepted"),
+    }
+}
+
+fn plain_addr(relay: &TestRelay) -> std::net::SocketAddr {
+    relay
+        .server
+        .local_addrs()
+        .iter()
+        .find_map(|addr| match addr {
+            ListenAddr::Plain(addr) => Some(*addr),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_server_proxy_protocol() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig =
+            serde_json::from_str(r#"{ "proxy_protocol": { "trusted_proxies": ["127.0.0.0/8"] } }"#)
+                .unwrap();
+        builder.listen_plain("127.0.0.1:0").config(config)
+    })
+    .await;
+
+    let mut client = TestClient::from_stream(TcpStream::connect(plain_addr(&relay)).await.unwrap());
+    client
+        .writer
+        .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 51234 6969\r\n")
+        .await
+        .unwrap();
+    client.send(connect("foo")).await;
+    assert_eq!(client.recv().await, Some(MessageType::HEY));
+
+    // trusted proxies must send a header
+    let mut client = TestClient::from_stream(TcpStream::connect(plain_addr(&relay)).await.unwrap());
+    client.send(connect("foo")).await;
+    assert_eq!(client.recv().await, None);
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_proxy_protocol_untrusted() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig =
+            serde_json::from_str(r#"{ "proxy_protocol": { "trusted_proxies": ["10.0.0.0/8"] } }"#)
+                .unwrap();
+        builder.listen_plain("127.0.0.1:0").config(config)
+    })
+    .await;
+
+    // direct connections work as usual
+    let mut client = TestClient::from_stream(TcpStream::connect(plain_addr(&relay)).await.unwrap());
+    client.send(connect("foo")).await;
+    assert_eq!(client.recv().await, Some(MessageType::HEY));
+
+    // spoofed headers aren't parsed, they're just a malformed first command
+    let mut client = TestClient::from_stream(TcpStream::connect(plain_addr(&relay)).await.unwrap());
+    client
+        .writer
+        .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 51234 6969\r\nCONNECT foo bar\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        client.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::MalformedCommand))
+    );
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_multiple_listeners() {
+    let relay = TestRelay::start_with(&["foo", "foo2"], |builder, _| {
+        builder.listen_plain("127.0.0.1:0").listen_plain("[::1]:0")
+    })
+    .await;
+
+    let plain_addrs: Vec<_> = relay
+        .server
+        .local_addrs()
+        .iter()
+        .filter_map(|addr| match addr {
+            ListenAddr::Plain(addr) => Some(*addr),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(plain_addrs.len(), 2);
+    assert!(plain_addrs[0].is_ipv4() && plain_addrs[1].is_ipv6());
+
+    let mut foo = TestClient::from_stream(TcpStream::connect(plain_addrs[0]).await.unwrap());
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::from_stream(TcpStream::connect(plain_addrs[1]).await.unwrap());
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    // all listeners share the same relay state
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    assert_eq!(foo.recv_boop().await, "foo2");
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_connection_limits() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig =
+            serde_json::from_str(r#"{ "connection_limits": { "per_ip": 2 } }"#).unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let mut first = TestClient::connect(&relay).await;
+    first.send(connect("foo")).await;
+    assert_eq!(first.recv().await, Some(MessageType::HEY));
+    let _second = TestClient::connect(&relay).await;
+
+    let mut third = TestClient::connect(&relay).await;
+    assert_eq!(
+        third.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::TooManyConnections))
+    );
+    assert_eq!(third.recv().await, None);
+
+    // closed connections make room again
+    first.send(MessageType::DISCONNECT).await;
+    assert_eq!(first.recv().await, Some(MessageType::BYE));
+    assert_eq!(first.recv().await, None);
+    let mut fourth = TestClient::connect(&relay).await;
+    fourth.send(connect("foo")).await;
+    assert_eq!(fourth.recv().await, Some(MessageType::HEY));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_connection_limits_proxied() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig = serde_json::from_str(
+            r#"{ "proxy_protocol": { "trusted_proxies": ["127.0.0.0/8"] },
+                "connection_limits": { "per_ip": 1 } }"#,
+        )
+        .unwrap();
+        builder.listen_plain("127.0.0.1:0").config(config)
+    })
+    .await;
+
+    async fn proxied(relay: &TestRelay, client_ip: &str) -> TestClient {
+        let mut client =
+            TestClient::from_stream(TcpStream::connect(plain_addr(relay)).await.unwrap());
+        let header = format!("PROXY TCP4 {} 127.0.0.1 51234 6969\r\n", client_ip);
+        client.writer.write_all(header.as_bytes()).await.unwrap();
+        client
+    }
+
+    // connections through the proxy are limited by the client address, not the proxy's
+    let mut first = proxied(&relay, "203.0.113.7").await;
+    first.send(connect("foo")).await;
+    assert_eq!(first.recv().await, Some(MessageType::HEY));
+    let mut second = proxied(&relay, "203.0.113.8").await;
+    second.send(connect("foo")).await;
+    assert_eq!(second.recv().await, Some(MessageType::HEY));
+
+    let mut third = proxied(&relay, "203.0.113.7").await;
+    assert_eq!(
+        third.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::TooManyConnections))
+    );
+    assert_eq!(third.recv().await, None);
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_session_limit() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig =
+            serde_json::from_str(r#"{ "connection_limits": { "sessions_per_key": 1 } }"#).unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let mut phone = TestClient::connect(&relay).await;
+    phone.send(connect("foo")).await;
+    assert_eq!(phone.recv().await, Some(MessageType::HEY));
+
+    let mut laptop = TestClient::connect(&relay).await;
+    laptop.send(connect("foo")).await;
+    assert_eq!(
+        laptop.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::TooManySessions))
+    );
+    assert_eq!(laptop.recv().await, None);
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_session_limit_evicts_oldest() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig = serde_json::from_str(
+            r#"{ "connection_limits": { "sessions_per_key": 1, "evict_oldest": true } }"#,
+        )
+        .unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let mut phone = TestClient::connect(&relay).await;
+    phone.send(connect("foo")).await;
+    assert_eq!(phone.recv().await, Some(MessageType::HEY));
+
+    let mut laptop = TestClient::connect(&relay).await;
+    laptop.send(connect("foo")).await;
+    assert_eq!(laptop.recv().await, Some(MessageType::HEY));
+
+    assert_eq!(
+        phone.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::TooManySessions))
+    );
+    assert_eq!(phone.recv().await, None);
+
+    laptop.send(MessageType::PING).await;
+    assert_eq!(laptop.recv().await, Some(MessageType::PONG));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_keepalive_negotiation() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig =
+            serde_json::from_str(r#"{ "keepalive": { "timeout": 1, "min": 1, "max": 300 } }"#)
+                .unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let mut phone = TestClient::connect(&relay).await;
+    phone
+        .send(MessageType::CONNECT(
+            String::from("foo"),
+            String::from(PASSWORD),
+            LoginOptions {
+                keepalive: Some(3600),
+                ..LoginOptions::default()
+            },
+        ))
+        .await;
+    assert_eq!(phone.recv().await, Some(MessageType::HEY));
+    assert_eq!(phone.recv().await, Some(MessageType::KEEPALIVE(300)));
+
+    // clients without a requested interval get the default timeout
+    let mut laptop = TestClient::connect(&relay).await;
+    laptop.send(connect("foo")).await;
+    assert_eq!(laptop.recv().await, Some(MessageType::HEY));
+
+    // any command counts as activity
+    for _ in 0..3 {
+        tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+        laptop.send(MessageType::AYT(String::from("foo"))).await;
+        assert_eq!(
+            laptop.recv().await,
+            Some(MessageType::ONLINE(String::from("foo"), Vec::new()))
+        );
+    }
+    assert_eq!(laptop.recv().await, None);
+
+    phone.send(MessageType::PING).await;
+    assert_eq!(phone.recv().await, Some(MessageType::PONG));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_keepalive_huge_interval() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig = serde_json::from_str(
+            r#"{ "keepalive": { "timeout": 18446744073709551615, "server_ping": true,
+                "ping_timeout": 18446744073709551615 } }"#,
+        )
+        .unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let mut client = TestClient::connect(&relay).await;
+    client.send(connect("foo")).await;
+    assert_eq!(client.recv().await, Some(MessageType::HEY));
+
+    // restarting the watchdog must not overflow
+    client.send(MessageType::PING).await;
+    assert_eq!(client.recv().await, Some(MessageType::PONG));
+    client.send(MessageType::PING).await;
+    assert_eq!(client.recv().await, Some(MessageType::PONG));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_keepalive_server_ping() {
+    let relay = TestRelay::start_with(&["foo"], |builder, _| {
+        let config: RelayConfig = serde_json::from_str(
+            r#"{ "keepalive": { "timeout": 1, "server_ping": true, "ping_timeout": 1 } }"#,
+        )
+        .unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let mut client = TestClient::connect(&relay).await;
+    client.send(connect("foo")).await;
+    assert_eq!(client.recv().await, Some(MessageType::HEY));
+
+    // answered pings keep the connection open
+    assert_eq!(client.recv().await, Some(MessageType::PING));
+    client.send(MessageType::PONG).await;
+    assert_eq!(client.recv().await, Some(MessageType::PING));
+
+    // unanswered pings close it
+    assert_eq!(client.recv().await, None);
+
+    relay.stop().await;
+}
+
+fn connect_ids(key: &str, device: Option<&str>) -> MessageType {
+    MessageType::CONNECT(
+        String::from(key),
+        String::from(PASSWORD),
+        LoginOptions {
+            device: device.map(String::from),
+            ids: true,
+            ..LoginOptions::default()
+        },
+    )
+}
+
+fn connect_device(key: &str, device: &str) -> MessageType {
+    MessageType::CONNECT(
+        String::from(key),
+        String::from(PASSWORD),
+        LoginOptions {
+            device: Some(String::from(device)),
+            ..LoginOptions::default()
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_server_devices() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let mut phone = TestClient::connect(&relay).await;
+    phone.send(connect_device("foo", "phone")).await;
+    assert_eq!(phone.recv().await, Some(MessageType::HEY));
+
+    let mut laptop = TestClient::connect(&relay).await;
+    laptop.send(connect_device("foo", "laptop")).await;
+    assert_eq!(laptop.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    foo2.send(MessageType::DEVICES(String::from("foo"))).await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::ONLINE(
+            String::from("foo"),
+            vec![String::from("laptop"), String::from("phone")]
+        ))
+    );
+    foo2.send(MessageType::DEVICES(String::from("foo3"))).await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::AFK(String::from("foo3"), None))
+    );
+
+    // only the phone gets the targeted boop
+    foo2.send(MessageType::BOOP(String::from("foo/phone"), None))
+        .await;
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    assert_eq!(phone.recv_boop().await, "foo2");
+    assert_eq!(phone.recv_boop().await, "foo2");
+    assert_eq!(laptop.recv_boop().await, "foo2");
+
+    laptop.send(MessageType::PING).await;
+    assert_eq!(laptop.recv().await, Some(MessageType::PONG));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_presence_status() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let mut phone = TestClient::connect(&relay).await;
+    phone.send(connect_device("foo", "phone")).await;
+    assert_eq!(phone.recv().await, Some(MessageType::HEY));
+
+    let mut laptop = TestClient::connect(&relay).await;
+    laptop.send(connect_device("foo", "laptop")).await;
+    assert_eq!(laptop.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(MessageType::CONNECT(
+        String::from("foo2"),
+        String::from(PASSWORD),
+        LoginOptions {
+            presence: true,
+            ..LoginOptions::default()
+        },
+    ))
+    .await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    phone
+        .send(MessageType::STATUS(
+            PresenceState::Busy,
+            Some(String::from("in a meeting")),
+        ))
+        .await;
+    laptop
+        .send(MessageType::STATUS(PresenceState::Away, None))
+        .await;
+    // the relay doesn't answer STATUS, a PING makes sure both were handled
+    for client in [&mut phone, &mut laptop] {
+        client.send(MessageType::PING).await;
+        assert_eq!(client.recv().await, Some(MessageType::PONG));
+    }
+
+    // the most available device wins
+    foo2.send(MessageType::AYT(String::from("foo"))).await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::ONLINE(String::from("foo"), Vec::new()))
+    );
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::PRESENCE(
+            String::from("foo"),
+            PresenceState::Busy,
+            Some(String::from("in a meeting"))
+        ))
+    );
+
+    phone.send(MessageType::DISCONNECT).await;
+    assert_eq!(phone.recv().await, Some(MessageType::BYE));
+    assert_eq!(phone.recv().await, None);
+
+    foo2.send(MessageType::AYT(String::from("foo"))).await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::ONLINE(String::from("foo"), Vec::new()))
+    );
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::PRESENCE(
+            String::from("foo"),
+            PresenceState::Away,
+            None
+        ))
+    );
+
+    // without the login option AYT is answered with ONLINE only
+    let mut foo3 = TestClient::connect(&relay).await;
+    foo3.send(connect("foo2")).await;
+    assert_eq!(foo3.recv().await, Some(MessageType::HEY));
+    foo3.send(MessageType::AYT(String::from("foo"))).await;
+    assert_eq!(
+        foo3.recv().await,
+        Some(MessageType::ONLINE(String::from("foo"), Vec::new()))
+    );
+    foo3.send(MessageType::PING).await;
+    assert_eq!(foo3.recv().await, Some(MessageType::PONG));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_do_not_disturb() {
+    let relay = TestRelay::start(&["foo", "foo2", "foo3"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    let mut foo3 = TestClient::connect(&relay).await;
+    foo3.send(connect("foo3")).await;
+    assert_eq!(foo3.recv().await, Some(MessageType::HEY));
+
+    foo.send(MessageType::DND(DndCommand::NotifySenders(true)))
+        .await;
+    foo.send(MessageType::DND(DndCommand::Enabled(true))).await;
+    foo.send(MessageType::PING).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PONG));
+
+    for _ in 0..2 {
+        foo2.send(MessageType::BOOP(String::from("foo"), None))
+            .await;
+        assert_eq!(
+            foo2.recv().await,
+            Some(MessageType::DEFERRED(String::from("foo")))
+        );
+    }
+    foo3.send(MessageType::BOOP(String::from("foo/phone"), None))
+        .await;
+    assert_eq!(
+        foo3.recv().await,
+        Some(MessageType::DEFERRED(String::from("foo")))
+    );
+
+    // nothing was delivered, the settings survive a reconnect
+    foo.send(MessageType::DISCONNECT).await;
+    assert_eq!(foo.recv().await, Some(MessageType::BYE));
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+    foo.send(MessageType::PING).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PONG));
+    assert!(relay.server.settings().dnd("foo").await.enabled);
+
+    // the held boops arrive as a summary once DND ends
+    foo.send(MessageType::DND(DndCommand::Enabled(false))).await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::BOOPS(String::from("foo2"), 2))
+    );
+    assert_eq!(foo.recv_boop().await, "foo3");
+
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    assert_eq!(foo.recv_boop().await, "foo2");
+    assert!(relay.server.stats().snapshot().contains(&("boops_held", 3)));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_quiet_hours() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    // quiet hours around the current time
+    let now = chrono::Utc::now();
+    let quiet_hours = format!(
+        "{}-{} UTC",
+        (now - chrono::Duration::hours(1)).format("%H:%M"),
+        (now + chrono::Duration::hours(1)).format("%H:%M")
+    );
+    foo.send(MessageType::DND(DndCommand::QuietHours(Some(
+        quiet_hours.parse().unwrap(),
+    ))))
+    .await;
+    foo.send(MessageType::PING).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PONG));
+
+    // senders aren't told by default
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    foo2.send(MessageType::PING).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::PONG));
+    foo.send(MessageType::PING).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PONG));
+
+    // saved with the user's settings, the operator's clients file stays untouched
+    let settings = tokio::fs::read_to_string(relay.dir.join("settings.json"))
+        .await
+        .unwrap();
+    assert!(settings.contains(&quiet_hours));
+    let clients = tokio::fs::read_to_string(relay.dir.join("clients.json"))
+        .await
+        .unwrap();
+    assert!(!clients.contains(&quiet_hours));
+
+    foo.send(MessageType::DND(DndCommand::QuietHours(None)))
+        .await;
+    assert_eq!(foo.recv_boop().await, "foo2");
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_last_seen() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    let before = boop_relay::unix_time();
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+    foo.send(MessageType::DISCONNECT).await;
+    assert_eq!(foo.recv().await, Some(MessageType::BYE));
+    assert_eq!(foo.recv().await, None);
+
+    // the time is saved right after the connection is closed
+    let last_seen = loop {
+        foo2.send(MessageType::AYT(String::from("foo"))).await;
+        match foo2.recv().await {
+            Some(MessageType::AFK(_, Some(last_seen))) => break last_seen,
+            Some(MessageType::AFK(_, None)) => {}
+            // not unregistered yet
+            Some(MessageType::ONLINE(..)) => {}
+            msg => panic!("unexpected answer: {:?}", msg),
+        }
+    };
+    assert!(last_seen >= before && last_seen <= boop_relay::unix_time());
+
+    // and survives a restart
+    let relay = relay.restart().await;
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+    foo2.send(MessageType::AYT(String::from("foo"))).await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::AFK(String::from("foo"), Some(last_seen)))
+    );
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+    foo.send(MessageType::LASTSEEN(false)).await;
+    foo.send(MessageType::DISCONNECT).await;
+    assert_eq!(foo.recv().await, Some(MessageType::BYE));
+    assert_eq!(foo.recv().await, None);
+
+    loop {
+        foo2.send(MessageType::AYT(String::from("foo"))).await;
+        match foo2.recv().await {
+            Some(MessageType::AFK(_, last_seen)) => break assert_eq!(last_seen, None),
+            Some(MessageType::ONLINE(..)) => {}
+            msg => panic!("unexpected answer: {:?}", msg),
+        }
+    }
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_block_list() {
+    let relay = TestRelay::start(&["foo", "foo2", "foo3"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    foo.send(MessageType::BLOCK(String::from("foo2"))).await;
+    foo.send(MessageType::BLOCK(String::from("foo3"))).await;
+    foo.send(MessageType::UNBLOCK(String::from("foo3"))).await;
+    foo.send(MessageType::BLOCKED(Vec::new())).await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::BLOCKED(vec![String::from("foo2")]))
+    );
+
+    // blocks survive a restart
+    let relay = relay.restart().await;
+    assert_eq!(foo.recv().await, None);
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    // the blocked key sees foo as offline and its boops are dropped
+    foo2.send(MessageType::AYT(String::from("foo"))).await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::AFK(String::from("foo"), None))
+    );
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    foo2.send(MessageType::PING).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::PONG));
+    foo.send(MessageType::PING).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PONG));
+
+    // foo itself can still check on foo2
+    foo.send(MessageType::AYT(String::from("foo2"))).await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::ONLINE(String::from("foo2"), Vec::new()))
+    );
+
+    foo.send(MessageType::UNBLOCK(String::from("foo2"))).await;
+    foo.send(MessageType::BLOCKED(Vec::new())).await;
+    assert_eq!(foo.recv().await, Some(MessageType::BLOCKED(Vec::new())));
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    assert_eq!(foo.recv_boop().await, "foo2");
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_history() {
+    let relay = TestRelay::start_with(&["foo", "foo2", "foo3"], |builder, dir| {
+        builder.history(dir.join("history.redb"))
+    })
+    .await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect_ids("foo", None)).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let mut phone = TestClient::connect(&relay).await;
+    phone.send(connect_device("foo2", "phone")).await;
+    assert_eq!(phone.recv().await, Some(MessageType::HEY));
+
+    let before = boop_relay::unix_time();
+    foo.send(MessageType::BOOP(String::from("foo2"), None))
+        .await;
+    assert_eq!(phone.recv_boop().await, "foo");
+    phone
+        .send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    let id = match foo.recv().await {
+        Some(MessageType::BOOP(source, Some(id))) if source == "foo2" => id,
+        msg => panic!("unexpected message: {:?}", msg),
+    };
+    foo.send(MessageType::BOOP(String::from("foo2/phone"), None))
+        .await;
+    assert_eq!(phone.recv_boop().await, "foo");
+    foo.send(MessageType::BOOPBACK(None, id)).await;
+    assert_eq!(phone.recv_boop().await, "foo");
+    // not relayed, not recorded
+    foo.send(MessageType::BOOP(String::from("foo3"), None))
+        .await;
+
+    // the history survives a restart
+    let relay = relay.restart().await;
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    foo2.send(MessageType::HISTORY(String::from("foo"), None))
+        .await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::ENTRIES(String::from("foo"), 4))
+    );
+    let mut entries = Vec::new();
+    for _ in 0..4 {
+        match foo2.recv().await {
+            Some(MessageType::ENTRY(entry)) => {
+                assert!(entry.timestamp >= before && entry.timestamp <= boop_relay::unix_time());
+                entries.push((entry.from, entry.to, entry.kind));
+            }
+            msg => panic!("unexpected answer: {:?}", msg),
+        }
+    }
+    let pair = |from: &str, to: &str, kind| (String::from(from), String::from(to), kind);
+    assert_eq!(
+        entries,
+        vec![
+            pair("foo", "foo2", BoopKind::Boop),
+            pair("foo2", "foo", BoopKind::Boop),
+            pair("foo", "foo2", BoopKind::Device),
+            pair("foo", "foo2", BoopKind::BoopBack)
+        ]
+    );
+
+    foo2.send(MessageType::HISTORY(
+        String::from("foo"),
+        Some(boop_relay::unix_time() + 1),
+    ))
+    .await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::ENTRIES(String::from("foo"), 0))
+    );
+    foo2.send(MessageType::HISTORY(String::from("foo3"), None))
+        .await;
+    assert_eq!(
+        foo2.recv().await,
+        Some(MessageType::ENTRIES(String::from("foo3"), 0))
+    );
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_scheduled_boops() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let later = boop_relay::unix_time() + 3600;
+    foo.send(MessageType::SCHEDULE(
+        String::from("foo2"),
+        BoopTime::At(later),
+    ))
+    .await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::SCHEDULED(0, String::from("foo2"), later))
+    );
+    // further ahead than max_delay_days
+    foo.send(MessageType::SCHEDULE(
+        String::from("foo2"),
+        BoopTime::In(400 * 24 * 60 * 60),
+    ))
+    .await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::MalformedArguments))
+    );
+
+    // scheduled boops survive a restart
+    let relay = relay.restart().await;
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    foo.send(MessageType::PENDING(None)).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PENDING(Some(1))));
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::SCHEDULED(0, String::from("foo2"), later))
+    );
+
+    // delivered like a boop sent right now
+    let before = boop_relay::unix_time();
+    foo.send(MessageType::SCHEDULE(String::from("foo2"), BoopTime::In(1)))
+        .await;
+    match foo.recv().await {
+        Some(MessageType::SCHEDULED(1, partner_key, time)) => {
+            assert_eq!(partner_key, "foo2");
+            assert!(time > before && time <= boop_relay::unix_time() + 1);
+        }
+        msg => panic!("unexpected answer: {:?}", msg),
+    }
+    assert_eq!(foo2.recv_boop().await, "foo");
+    assert!(boop_relay::unix_time() > before);
+
+    // only pending boops can be cancelled
+    foo.send(MessageType::CANCEL(0)).await;
+    foo.send(MessageType::PING).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PONG));
+    foo.send(MessageType::CANCEL(1)).await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::NotAvailable))
+    );
+    foo.send(MessageType::PENDING(None)).await;
+    assert_eq!(foo.recv().await, Some(MessageType::PENDING(Some(0))));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_scheduled_boops_of_banned_sender() {
+    let relay = TestRelay::start(&["foo", "foo2"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+    foo.send(MessageType::SCHEDULE(String::from("foo2"), BoopTime::In(2)))
+        .await;
+    assert!(matches!(
+        foo.recv().await,
+        Some(MessageType::SCHEDULED(0, _, _))
+    ));
+
+    // banned while the boop was pending
+    let clients_path = relay.dir.join("clients.json");
+    let mut clients = read_clients_file(&clients_path).await.unwrap();
+    clients[0].ban = Some(Ban {
+        until: None,
+        reason: None,
+    });
+    write_clients_file(&clients_path, &clients).await.unwrap();
+    let relay = relay.restart().await;
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+    foo2.send(MessageType::PING).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::PONG));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_boop_back() {
+    let relay = TestRelay::start(&["foo", "foo2", "foo3"]).await;
+
+    let mut phone = TestClient::connect(&relay).await;
+    phone.send(connect_ids("foo", Some("phone"))).await;
+    assert_eq!(phone.recv().await, Some(MessageType::HEY));
+    let mut laptop = TestClient::connect(&relay).await;
+    laptop.send(connect_ids("foo", Some("laptop"))).await;
+    assert_eq!(laptop.recv().await, Some(MessageType::HEY));
+    // without the login option boops come without IDs, and boop-backs as plain boops
+    let mut tablet = TestClient::connect(&relay).await;
+    tablet.send(connect_device("foo", "tablet")).await;
+    assert_eq!(tablet.recv().await, Some(MessageType::HEY));
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect_ids("foo2", None)).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+    let mut foo3 = TestClient::connect(&relay).await;
+    foo3.send(connect("foo3")).await;
+    assert_eq!(foo3.recv().await, Some(MessageType::HEY));
+
+    phone
+        .send(MessageType::BOOP(String::from("foo2"), None))
+        .await;
+    let first = match foo2.recv().await {
+        Some(MessageType::BOOP(source, Some(id))) if source == "foo" => id,
+        msg => panic!("unexpected message: {:?}", msg),
+    };
+    phone
+        .send(MessageType::BOOP(String::from("foo2"), None))
+        .await;
+    let second = match foo2.recv().await {
+        Some(MessageType::BOOP(source, Some(id))) if source == "foo" => id,
+        msg => panic!("unexpected message: {:?}", msg),
+    };
+    assert_ne!(first, second);
+
+    // the boop-back reaches every device of the booper with the ID of the answered boop
+    foo2.send(MessageType::BOOPBACK(None, first)).await;
+    for device in [&mut phone, &mut laptop] {
+        assert_eq!(
+            device.recv().await,
+            Some(MessageType::BOOPBACK(Some(String::from("foo2")), first))
+        );
+    }
+    assert_eq!(tablet.recv_boop().await, "foo2");
+
+    // only the recipient can answer a boop
+    foo3.send(MessageType::BOOPBACK(None, second)).await;
+    assert_eq!(
+        foo3.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::NotAvailable))
+    );
+    // relayed boops can't be sent by clients
+    foo3.send(MessageType::BOOP(String::from("foo"), Some(second)))
+        .await;
+    assert_eq!(
+        foo3.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::ProtocolMismatch))
+    );
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_pair_stats() {
+    let relay = TestRelay::start(&["foo", "foo2", "foo3"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    for _ in 0..2 {
+        foo.send(MessageType::BOOP(String::from("foo2"), None))
+            .await;
+        assert_eq!(foo2.recv_boop().await, "foo");
+    }
+    foo2.send(MessageType::BOOP(String::from("foo"), None))
+        .await;
+    assert_eq!(foo.recv_boop().await, "foo2");
+    // not relayed, not counted
+    foo.send(MessageType::BOOP(String::from("foo3"), None))
+        .await;
+
+    // the counters survive a restart
+    let relay = relay.restart().await;
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    let counts = |from: &str, to: &str, total, today, streak, longest_streak| {
+        MessageType::COUNTS(PairCounts {
+            from: String::from(from),
+            to: String::from(to),
+            total,
+            today,
+            streak,
+            longest_streak,
+        })
+    };
+    foo.send(MessageType::STATS(String::from("foo2"))).await;
+    assert_eq!(foo.recv().await, Some(counts("foo", "foo2", 2, 2, 1, 1)));
+    assert_eq!(foo.recv().await, Some(counts("foo2", "foo", 1, 1, 1, 1)));
+
+    foo.send(MessageType::STATS(String::from("foo3"))).await;
+    assert_eq!(foo.recv().await, Some(counts("foo", "foo3", 0, 0, 0, 0)));
+    assert_eq!(foo.recv().await, Some(counts("foo3", "foo", 0, 0, 0, 0)));
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn test_server_history_disabled() {
+    let relay = TestRelay::start(&["foo"]).await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+
+    foo.send(MessageType::HISTORY(String::from("foo2"), None))
+        .await;
+    assert_eq!(
+        foo.recv().await,
+        Some(MessageType::ERROR(MessageErrorKind::NotAvailable))
+    );
+
+    relay.stop().await;
+}
+
+/// Stand-in webhook endpoint. Answers the first request with 500 and every other one with
+/// 200, and passes the signature header and the body of every request on.
+async fn webhook_endpoint() -> (String, mpsc::UnboundedReceiver<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+    let (requests, requests_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut first = true;
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            stream.read_line(&mut request_line).await.unwrap();
+            assert_eq!(request_line, "POST /hooks HTTP/1.1\r\n");
+            let (mut signature, mut length) = (String::new(), 0);
+            loop {
+                let mut header = String::new();
+                stream.read_line(&mut header).await.unwrap();
+                match header.trim_end().split_once(": ") {
+                    Some(("X-Boop-Signature", value)) => signature = String::from(value),
+                    Some(("Content-Length", value)) => length = value.parse().unwrap(),
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await.unwrap();
+
+            let status = if first {
+                "500 Internal Server Error"
+            } else {
+                "200 OK"
+            };
+            first = false;
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            let _ = requests.send((signature, String::from_utf8(body).unwrap()));
+        }
+    });
+
+    (url, requests_rx)
+}
+
+static WEBHOOK_URL: OnceLock<String> = OnceLock::new();
+
+#[tokio::test]
+async fn test_server_webhooks() {
+    let (url, mut requests) = webhook_endpoint().await;
+    WEBHOOK_URL.set(url).unwrap();
+    let relay = TestRelay::start_with(&["foo", "foo2", "foo3"], |builder, _| {
+        let config: RelayConfig = serde_json::from_value(serde_json::json!({
+            "webhooks": {
+                "endpoints": [{ "url": WEBHOOK_URL.get().unwrap(), "secret": "s3cret" }],
+                "retry_delay": 0
+            }
+        }))
+        .unwrap();
+        builder.config(config)
+    })
+    .await;
+
+    let mut foo = TestClient::connect(&relay).await;
+    foo.send(connect("foo")).await;
+    assert_eq!(foo.recv().await, Some(MessageType::HEY));
+    let mut wrong = TestClient::connect(&relay).await;
+    wrong
+        .send(MessageType::CONNECT(
+            String::from("foo2"),
+            String::from("wrong"),
+            LoginOptions::default(),
+        ))
+        .await;
+    assert_eq!(wrong.recv().await, Some(MessageType::NO));
+    let mut foo2 = TestClient::connect(&relay).await;
+    foo2.send(connect("foo2")).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::HEY));
+
+    foo.send(MessageType::BOOP(String::from("foo2"), None))
+        .await;
+    assert_eq!(foo2.recv_boop().await, "foo");
+    foo.send(MessageType::BOOP(String::from("foo3"), None))
+        .await;
+    foo2.send(MessageType::DISCONNECT).await;
+    assert_eq!(foo2.recv().await, Some(MessageType::BYE));
+
+    let online = WebhookEvent::Online {
+        key: String::from("foo"),
+    };
+    let expected = [
+        // the first request fails and is retried
+        online.clone(),
+        online,
+        WebhookEvent::LoginFailed {
+            key: String::from("foo2"),
+            addr: String::from("127.0.0.1"),
+        },
+        WebhookEvent::Online {
+            key: String::from("foo2"),
+        },
+        WebhookEvent::BoopRelayed {
+            from: String::from("foo"),
+            to: String::from("foo2"),
+            device: None,
+        },
+        WebhookEvent::BoopUndeliverable {
+            from: String::from("foo"),
+            to: String::from("foo3"),
+            device: None,
+        },
+        WebhookEvent::Offline {
+            key: String::from("foo2"),
+        },
+    ];
+    for expected in expected {
+        let (signature, body) = requests.recv().await.unwrap();
+        assert_eq!(signature, format!("sha256={}", sign("s3cret", &body)));
+
+        let mut event: WebhookEvent = serde_json::from_str(&body).unwrap();
+        // the port of the failed login differs on every run
+        if let WebhookEvent::LoginFailed { addr, .. } = &mut event {
+            *addr = String::from(addr.rsplit_once(':').unwrap().0);
+        }
+        assert_eq!(event, expected);
+    }
+
+    relay.stop().await;
+}